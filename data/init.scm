@@ -1,155 +1,129 @@
-;; a short form for defining functions
-(defmacro defn (name args body)
-  `(def ,name   (lambda ,name ,args
-                        ,body)))
+;; procedures on lists
+(define-library (rscheme list)
+  (export fold reverse map count)
+  (begin
+    (define (fold f acc xs)
+      (if (_nil? xs)
+          acc
+          (fold f (f acc (_car xs)) (_cdr xs))))
 
+    (define (reverse xs)
+      (fold (lambda (acc x) (_cons x acc)) () xs))
 
+    (define (map f xs)
+      (reverse (fold (lambda (acc x) (_cons (f x) acc)) () xs)))
 
-(defn car (xs)
-  (_car xs))
+    (define (count xs)
+      (fold (lambda (n x) (_+ n 1)) 0 xs))))
 
-(defn cdr (xs)
-  (_cdr xs))
 
-(defn cadr (xs)
-  (car (cdr xs)))
 
-(defn cddr (xs)
-  (cdr (cdr xs)))
+(define-library (rscheme base)
+  (import (rscheme list))
+  (export defn car cdr cadr cddr cons apply = nil? list? boolean? lambda?
+          primitive? integer? float? ident? string? quote? quasiquote?
+          unquote? + - * / < > cond or and not <= >= inc str println)
+  (begin
+    ;; a short form for defining functions
+    (defmacro defn (name args & body)
+      `(define ,name (lambda ,name ,args ,@body)))
 
-(defn cons (x xs)
-  (_cons x xs))
+    (def car _car)
 
-(defn apply (f args)
-  (eval (cons f args)))
+    (def cdr _cdr)
 
-(defn = (x y)
-  (_= x y))
+    (defn cadr (xs)
+      (car (cdr xs)))
 
-(defn nil? (xs)
-  (_nil? xs))
+    (defn cddr (xs)
+      (cdr (cdr xs)))
 
-(defn list? (xs)
-  (_list? xs))
+    (def cons _cons)
 
-(defn lambda? (xs)
-  (_lambda? xs))
+    (def apply _apply)
 
-(defn integer? (xs)
-  (_integer? xs))
+    (def = _=)
 
-(defn float? (xs)
-  (_float? xs))
+    (def nil? _nil?)
 
-(defn ident? (xs)
-  (_ident? xs))
+    (def list? _list?)
 
-(defn string? (xs)
-  (_string? xs))
+    (def boolean? _boolean?)
 
-(defn quote? (xs)
-  (_quote? xs))
+    (def lambda? _lambda?)
 
-(defn quasiquote? (xs)
-  (_quasiquote? xs))
+    (def primitive? _primitive?)
 
-(defn unquote? (xs)
-  (_unquote? xs))
+    (def integer? _integer?)
 
-(defn + (x & args)
-  (if (nil? args)
-      x
-      (_+ x (apply + args))))
+    (def float? _float?)
 
-(defn - (x y)
-  (_- x y))
+    (def ident? _ident?)
 
-(defn * (x y)
-  (_* x y))
+    (def string? _string?)
 
-(defn / (x y)
-  (_/ x y))
+    (def quote? _quote?)
 
-(defn < (x y)
-  (_< x y))
+    (def quasiquote? _quasiquote?)
 
-(defn > (x y)
-  (_> x y))
+    (def unquote? _unquote?)
 
-(defn count (xs)
-  (if (nil? xs)
-      0
-      (+ 1 (count (cdr xs)))))
+    (defn + (x & args)
+      (fold _+ x args))
 
-(defmacro cond (preds)
-  `(if ,(car (car preds))
-       ,(cadr (car preds))
-       ,(if (nil? (cdr preds))
-            ()
-            `(cond ,(cdr preds)))))
+    (def - _-)
 
-(defn do (& args)
-  (cond (((nil? args) ())
-         ((= 1 (count args)) (car args))
-         ('else (apply do (cdr args))))))
+    (defn * (x & args)
+      (fold _* x args))
 
-(defmacro define (name expr & exprs)
-  `(def ,(if (list? name)
-             (car name)
-             name)
-        ,(if (list? name)
-             `(lambda ,(car name) ,(cdr name)
-                      ,(if (nil? exprs)
-                           expr
-                           (cons do
-                                 (cons expr exprs))))
-             expr)))
+    (def / _/)
 
-(defmacro or (p1 p2)
-  `(if ,p1
-       't
-       ,p2))
+    (def < _<)
 
-(defmacro and (p1 p2)
-  `(if ,p1
-       ,p2
-       ()))
+    (def > _>)
 
-(defn <= (x y)
-  (or (< x y)
-      (= x y)))
+    (define-syntax cond
+      (syntax-rules (else)
+        ((_ (else e ...)) (begin e ...))
+        ((_ (test e ...)) (if test (begin e ...) ()))
+        ((_ (test e ...) clause ...) (if test (begin e ...) (cond clause ...)))))
 
-(defn >= (x y)
-  (or (> x y)
-      (= x y)))
+    (defmacro or (p1 p2)
+      `(if ,p1
+           #t
+           ,p2))
 
-(defn map (f xs)
-  (if (nil? xs)
-      ()
-      (cons (f (car xs))
-            (map f (cdr xs)))))
-
-(defn inc (x)
-  (+ x 1))
-
-(defmacro let (args body)
-  (cons `(lambda ,(map car args)
-           ,body)
-        (map cadr args)))
-
-(defn str (s & args)
-  (cond (((nil? args) s)
-         ((= 1 (count args)) (_str s (car args)))
-         ('else 
-         (_str s (apply str args))))))
-
-(defn println (s & args)
-  (cond (((nil? args) (_print (str s "\n")))
-         ((= 1 (count args)) (_print (str s " " (car args) "\n")))
-         ('else (apply println (cons s
-                                     (cons (str (car args) " " (cadr args))
-                                           (cddr args))))))))
-  
-
-
-  
+    (defmacro and (p1 p2)
+      `(if ,p1
+           ,p2
+           #f))
+
+    (defn not (x)
+      (if x #f #t))
+
+    (defn <= (x y)
+      (or (< x y)
+          (= x y)))
+
+    (defn >= (x y)
+      (or (> x y)
+          (= x y)))
+
+    (defn inc (x)
+      (+ x 1))
+
+    (defn str (s & args)
+      (cond ((nil? args) s)
+            ((= 1 (count args)) (_str s (car args)))
+            (else (_str s (apply str args)))))
+
+    (defn println (s & args)
+      (cond ((nil? args) (_print (str s "\n")))
+            ((= 1 (count args)) (_print (str s " " (car args) "\n")))
+            (else (apply println (cons s
+                                       (cons (str (car args) " " (cadr args))
+                                             (cddr args)))))))))
+
+
+
+(import (rscheme base) (rscheme list))
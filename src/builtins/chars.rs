@@ -0,0 +1,96 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Procedures on characters.
+
+use expr::Expr;
+use eval::Context;
+use error::EvalError;
+use primitive::Arity;
+use super::{Value, bad_type, boolean};
+
+use std::rc::Rc;
+use std::cmp::Ordering;
+
+fn get_char(name:&str, e:&Rc<Expr>) -> Result<char, EvalError> {
+    match **e {
+        Expr::Char(c) => Ok(c),
+        _ => Err(bad_type(name, e))
+    }
+}
+
+fn char_to_integer(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let c = get_char("char->integer", &args[0])?;
+    Ok(Rc::new(Expr::Integer(c as i64)))
+}
+
+fn integer_to_char(_:&Context, args:&[Rc<Expr>]) -> Value {
+    match *args[0] {
+        Expr::Integer(x) if x >= 0 && x <= u32::MAX as i64 => match ::std::char::from_u32(x as u32) {
+            Some(c) => Ok(Rc::new(Expr::Char(c))),
+            None => Err(bad_type("integer->char", &args[0]))
+        },
+        _ => Err(bad_type("integer->char", &args[0]))
+    }
+}
+
+fn char_upcase(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let c = get_char("char-upcase", &args[0])?;
+    Ok(Rc::new(Expr::Char(c.to_uppercase().next().unwrap_or(c))))
+}
+
+fn char_downcase(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let c = get_char("char-downcase", &args[0])?;
+    Ok(Rc::new(Expr::Char(c.to_lowercase().next().unwrap_or(c))))
+}
+
+// Check a property of a character
+fn char_check(name:&str, args:&[Rc<Expr>], f:fn(char) -> bool) -> Value {
+    let c = get_char(name, &args[0])?;
+    Ok(boolean(f(c)))
+}
+
+// Compare each character with the next one
+fn char_compare(name:&str, args:&[Rc<Expr>], f:fn(Ordering) -> bool) -> Value {
+    let mut prev = get_char(name, &args[0])?;
+    let mut res = true;
+    for arg in &args[1..] {
+        let c = get_char(name, arg)?;
+        res = res && f(prev.cmp(&c));
+        prev = c;
+    }
+    Ok(boolean(res))
+}
+
+/// Defines the procedures on characters
+pub fn register(c:&Context) {
+    c.define_primitive("char->integer", Arity::Exactly(1), char_to_integer);
+    c.define_primitive("integer->char", Arity::Exactly(1), integer_to_char);
+    c.define_primitive("char-upcase", Arity::Exactly(1), char_upcase);
+    c.define_primitive("char-downcase", Arity::Exactly(1), char_downcase);
+    c.define_primitive("char-alphabetic?", Arity::Exactly(1),
+                       |_, args| char_check("char-alphabetic?", args, char::is_alphabetic));
+    c.define_primitive("char-numeric?", Arity::Exactly(1),
+                       |_, args| char_check("char-numeric?", args, char::is_numeric));
+    c.define_primitive("char-whitespace?", Arity::Exactly(1),
+                       |_, args| char_check("char-whitespace?", args, char::is_whitespace));
+    c.define_primitive("char-upper-case?", Arity::Exactly(1),
+                       |_, args| char_check("char-upper-case?", args, char::is_uppercase));
+    c.define_primitive("char-lower-case?", Arity::Exactly(1),
+                       |_, args| char_check("char-lower-case?", args, char::is_lowercase));
+    c.define_primitive("char=?", Arity::AtLeast(1),
+                       |_, args| char_compare("char=?", args, |o| o == Ordering::Equal));
+    c.define_primitive("char<?", Arity::AtLeast(1),
+                       |_, args| char_compare("char<?", args, |o| o == Ordering::Less));
+    c.define_primitive("char>?", Arity::AtLeast(1),
+                       |_, args| char_compare("char>?", args, |o| o == Ordering::Greater));
+    c.define_primitive("char<=?", Arity::AtLeast(1),
+                       |_, args| char_compare("char<=?", args, |o| o != Ordering::Greater));
+    c.define_primitive("char>=?", Arity::AtLeast(1),
+                       |_, args| char_compare("char>=?", args, |o| o != Ordering::Less));
+}
@@ -0,0 +1,71 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Raising and handling exceptions. Any object can be raised; errors,
+//! whether signaled by `error` or by the evaluator, are raised as error
+//! objects. The `guard` form is a special form of the evaluator.
+
+use expr::Expr;
+use eval::Context;
+use error::EvalError;
+use primitive::Arity;
+use super::{Value, bad_type, boolean};
+
+use std::rc::Rc;
+
+fn get_error<'a>(name:&str, e:&'a Rc<Expr>) -> Result<&'a EvalError, EvalError> {
+    match **e {
+        Expr::Error(ref err) => Ok(err.cause()),
+        _ => Err(bad_type(name, e))
+    }
+}
+
+// (error message irritant...)
+fn error(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let message = match *args[0] {
+        Expr::String(ref s) => s.clone(),
+        _ => return Err(bad_type("error", &args[0]))
+    };
+    let mut irritants = Rc::new(Expr::Nil);
+    for e in args[1..].iter().rev() {
+        irritants = Rc::new(Expr::Cons(e.clone(), irritants));
+    }
+    c.raise(Rc::new(Expr::Error(EvalError::Error(message, irritants))), false)
+}
+
+// The message of an error of the evaluator is its description
+fn error_object_message(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let message = match *get_error("error-object-message", &args[0])? {
+        EvalError::Error(ref message, _) => message.clone(),
+        ref err => err.to_string()
+    };
+    Ok(Rc::new(Expr::String(message)))
+}
+
+// The irritant of an error of the evaluator is the offending expression
+fn error_object_irritants(_:&Context, args:&[Rc<Expr>]) -> Value {
+    match *get_error("error-object-irritants", &args[0])? {
+        EvalError::Error(_, ref irritants) => Ok(irritants.clone()),
+        EvalError::Syntax(_, _) => Ok(Rc::new(Expr::Nil)),
+        ref err => Ok(Rc::new(Expr::Cons(err.expr(), Rc::new(Expr::Nil))))
+    }
+}
+
+/// Defines the procedures raising and handling exceptions
+pub fn register(c:&Context) {
+    c.define_primitive("error", Arity::AtLeast(1), error);
+    c.define_primitive("raise", Arity::Exactly(1), |c, args| c.raise(args[0].clone(), false));
+    c.define_primitive("raise-continuable", Arity::Exactly(1),
+                       |c, args| c.raise(args[0].clone(), true));
+    c.define_primitive("with-exception-handler", Arity::Exactly(2),
+                       |c, args| c.with_exception_handler(args[0].clone(), args[1].clone()));
+    c.define_primitive("error-object?", Arity::Exactly(1),
+                       |_, args| Ok(boolean(matches!(*args[0], Expr::Error(_)))));
+    c.define_primitive("error-object-message", Arity::Exactly(1), error_object_message);
+    c.define_primitive("error-object-irritants", Arity::Exactly(1), error_object_irritants);
+}
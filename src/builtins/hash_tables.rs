@@ -0,0 +1,133 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Procedures on hash tables. Keys are compared with `equal?`; the order
+//! of the entries is unspecified.
+
+use expr::Expr;
+use eval::Context;
+use error::EvalError;
+use primitive::Arity;
+use table::{HashKey, Entry, Table};
+use super::{Value, bad_type, boolean};
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+fn get_table<'a>(name:&str, e:&'a Rc<Expr>) -> Result<&'a RefCell<Table>, EvalError> {
+    match **e {
+        Expr::HashTable(ref t) => Ok(t),
+        _ => Err(bad_type(name, e))
+    }
+}
+
+fn get_key(name:&str, e:&Rc<Expr>) -> Result<HashKey, EvalError> {
+    HashKey::new(e).ok_or_else(|| bad_type(name, e))
+}
+
+// The entries of a table, copied so that functions called on them can
+// modify the table
+fn entries(name:&str, e:&Rc<Expr>) -> Result<Vec<Entry>, EvalError> {
+    Ok(get_table(name, e)?.borrow().values().cloned().collect())
+}
+
+fn list(elems:Vec<Rc<Expr>>) -> Value {
+    let mut res = Rc::new(Expr::Nil);
+    for e in elems.into_iter().rev() {
+        res = Rc::new(Expr::Cons(e, res));
+    }
+    Ok(res)
+}
+
+fn make_hash_table(_:&Context, _:&[Rc<Expr>]) -> Value {
+    Ok(Rc::new(Expr::HashTable(RefCell::new(Table::new()))))
+}
+
+// Returns the value
+fn hash_table_set(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let t = get_table("hash-table-set!", &args[0])?;
+    let key = get_key("hash-table-set!", &args[1])?;
+    t.borrow_mut().insert(key, (args[1].clone(), args[2].clone()));
+    Ok(args[2].clone())
+}
+
+fn lookup(name:&str, args:&[Rc<Expr>]) -> Result<Option<Rc<Expr>>, EvalError> {
+    let t = get_table(name, &args[0])?;
+    let key = get_key(name, &args[1])?;
+    let res = t.borrow().get(&key).map(|(_, v)| v.clone());
+    Ok(res)
+}
+
+// (hash-table-ref t key [thunk]): thunk is called if key is not in t
+fn hash_table_ref(c:&Context, args:&[Rc<Expr>]) -> Value {
+    match (lookup("hash-table-ref", args)?, args.get(2)) {
+        (Some(v), _) => Ok(v),
+        (None, Some(thunk)) => c.apply(thunk.clone(), &[]),
+        (None, None) => Err(EvalError::KeyNotFound("hash-table-ref".to_string(), args[1].clone()))
+    }
+}
+
+fn hash_table_ref_default(_:&Context, args:&[Rc<Expr>]) -> Value {
+    Ok(lookup("hash-table-ref/default", args)?.unwrap_or_else(|| args[2].clone()))
+}
+
+fn hash_table_contains(_:&Context, args:&[Rc<Expr>]) -> Value {
+    Ok(boolean(lookup("hash-table-contains?", args)?.is_some()))
+}
+
+// Returns #t if the key was in the table
+fn hash_table_delete(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let t = get_table("hash-table-delete!", &args[0])?;
+    let key = get_key("hash-table-delete!", &args[1])?;
+    let removed = t.borrow_mut().remove(&key).is_some();
+    Ok(boolean(removed))
+}
+
+fn hash_table_count(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let t = get_table("hash-table-count", &args[0])?;
+    let n = t.borrow().len();
+    Ok(Rc::new(Expr::Integer(n as i64)))
+}
+
+fn hash_table_keys(_:&Context, args:&[Rc<Expr>]) -> Value {
+    list(entries("hash-table-keys", &args[0])?.into_iter().map(|(k, _)| k).collect())
+}
+
+fn hash_table_values(_:&Context, args:&[Rc<Expr>]) -> Value {
+    list(entries("hash-table-values", &args[0])?.into_iter().map(|(_, v)| v).collect())
+}
+
+// List of (key . value) pairs
+fn hash_table_to_alist(_:&Context, args:&[Rc<Expr>]) -> Value {
+    list(entries("hash-table->alist", &args[0])?.into_iter()
+         .map(|(k, v)| Rc::new(Expr::Cons(k, v)))
+         .collect())
+}
+
+// (hash-table-walk t f) calls (f key value) on each entry; returns t
+fn hash_table_walk(c:&Context, args:&[Rc<Expr>]) -> Value {
+    for (k, v) in entries("hash-table-walk", &args[0])? {
+        c.apply(args[1].clone(), &[k, v])?;
+    }
+    Ok(args[0].clone())
+}
+
+/// Defines the procedures on hash tables
+pub fn register(c:&Context) {
+    c.define_primitive("make-hash-table", Arity::Exactly(0), make_hash_table);
+    c.define_primitive("hash-table-set!", Arity::Exactly(3), hash_table_set);
+    c.define_primitive("hash-table-ref", Arity::Range(2, 3), hash_table_ref);
+    c.define_primitive("hash-table-ref/default", Arity::Exactly(3), hash_table_ref_default);
+    c.define_primitive("hash-table-contains?", Arity::Exactly(2), hash_table_contains);
+    c.define_primitive("hash-table-delete!", Arity::Exactly(2), hash_table_delete);
+    c.define_primitive("hash-table-count", Arity::Exactly(1), hash_table_count);
+    c.define_primitive("hash-table-keys", Arity::Exactly(1), hash_table_keys);
+    c.define_primitive("hash-table-values", Arity::Exactly(1), hash_table_values);
+    c.define_primitive("hash-table->alist", Arity::Exactly(1), hash_table_to_alist);
+    c.define_primitive("hash-table-walk", Arity::Exactly(2), hash_table_walk);
+}
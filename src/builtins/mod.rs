@@ -0,0 +1,287 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! The builtin primitives, defined in the global environment of every
+//! context.
+
+use expr::Expr;
+use eval::Context;
+use error::EvalError;
+use primitive::Arity;
+use number;
+use number::NumError;
+
+use std::rc::Rc;
+use std::cmp::Ordering;
+
+mod chars;
+mod exceptions;
+mod hash_tables;
+mod ports;
+mod strings;
+mod vectors;
+
+type Value = Result<Rc<Expr>, EvalError>;
+
+fn bad_type(name:&str, e:&Rc<Expr>) -> EvalError {
+    EvalError::TypeMismatch(name.to_string(), e.clone())
+}
+
+fn boolean(b:bool) -> Rc<Expr> {
+    Rc::new(Expr::Bool(b))
+}
+
+// Convert the error of an arithmetic operation
+fn num_error(c:&Context, name:&str, args:&[Rc<Expr>], err:NumError) -> EvalError {
+    match err {
+        NumError::NotANumber(i) => bad_type(name, &args[i]),
+        NumError::DivisionByZero => EvalError::DivisionByZero(c.expr.clone())
+    }
+}
+
+// Apply a binary numeric operation
+fn arith(c:&Context, name:&str, args:&[Rc<Expr>],
+         f:fn(&Expr, &Expr) -> Result<Expr, NumError>) -> Value {
+    match f(&args[0], &args[1]) {
+        Ok(e) => Ok(Rc::new(e)),
+        Err(err) => Err(num_error(c, name, args, err))
+    }
+}
+
+fn plus(c:&Context, args:&[Rc<Expr>]) -> Value {
+    arith(c, "_+", args, number::add)
+}
+
+fn sub(c:&Context, args:&[Rc<Expr>]) -> Value {
+    arith(c, "_-", args, number::sub)
+}
+
+fn mul(c:&Context, args:&[Rc<Expr>]) -> Value {
+    arith(c, "_*", args, number::mul)
+}
+
+fn div(c:&Context, args:&[Rc<Expr>]) -> Value {
+    arith(c, "_/", args, number::div)
+}
+
+fn quotient(c:&Context, args:&[Rc<Expr>]) -> Value {
+    arith(c, "quotient", args, number::quotient)
+}
+
+fn remainder(c:&Context, args:&[Rc<Expr>]) -> Value {
+    arith(c, "remainder", args, number::remainder)
+}
+
+fn modulo(c:&Context, args:&[Rc<Expr>]) -> Value {
+    arith(c, "modulo", args, number::modulo)
+}
+
+// Compare two numbers or two strings
+fn compare(c:&Context, name:&str, args:&[Rc<Expr>]) -> Result<Option<Ordering>, EvalError> {
+    match (&*args[0], &*args[1]) {
+        (Expr::String(s1), Expr::String(s2)) => Ok(s1.partial_cmp(s2)),
+        (Expr::String(_), _) => Err(bad_type(name, &args[1])),
+        (x, y) => number::compare(x, y).map_err(|err| num_error(c, name, args, err))
+    }
+}
+
+fn lt(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let ord = compare(c, "_<", args)?;
+    Ok(boolean(ord == Some(Ordering::Less)))
+}
+
+fn gt(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let ord = compare(c, "_>", args)?;
+    Ok(boolean(ord == Some(Ordering::Greater)))
+}
+
+// Numbers are compared by value, whatever their exactness; other values
+// structurally
+fn equal(_:&Context, args:&[Rc<Expr>]) -> Value {
+    if number::is_number(&args[0]) && number::is_number(&args[1]) {
+        let ord = number::compare(&args[0], &args[1]).ok();
+        Ok(boolean(ord == Some(Some(Ordering::Equal))))
+    } else {
+        Ok(boolean(args[0] == args[1]))
+    }
+}
+
+// Apply a conversion on a number
+fn convert(name:&str, args:&[Rc<Expr>], f:fn(&Expr) -> Option<Expr>) -> Value {
+    match f(&args[0]) {
+        Some(e) => Ok(Rc::new(e)),
+        None => Err(bad_type(name, &args[0]))
+    }
+}
+
+fn exact_to_inexact(_:&Context, args:&[Rc<Expr>]) -> Value {
+    convert("exact->inexact", args, number::to_inexact)
+}
+
+fn inexact_to_exact(_:&Context, args:&[Rc<Expr>]) -> Value {
+    convert("inexact->exact", args, number::to_exact)
+}
+
+fn numerator(_:&Context, args:&[Rc<Expr>]) -> Value {
+    convert("numerator", args, number::numerator)
+}
+
+fn denominator(_:&Context, args:&[Rc<Expr>]) -> Value {
+    convert("denominator", args, number::denominator)
+}
+
+fn car(_:&Context, args:&[Rc<Expr>]) -> Value {
+    match *args[0] {
+        Expr::Cons(ref car, _) => Ok(car.clone()),
+        _ => Err(bad_type("_car", &args[0]))
+    }
+}
+
+fn cdr(_:&Context, args:&[Rc<Expr>]) -> Value {
+    match *args[0] {
+        Expr::Cons(_, ref cdr) => Ok(cdr.clone()),
+        _ => Err(bad_type("_cdr", &args[0]))
+    }
+}
+
+fn cons(_:&Context, args:&[Rc<Expr>]) -> Value {
+    Ok(Rc::new(Expr::Cons(args[0].clone(), args[1].clone())))
+}
+
+// Strings are displayed without quotes
+fn to_string(e:&Expr) -> String {
+    match *e {
+        Expr::String(ref s) => s.clone(),
+        Expr::Char(c) => c.to_string(),
+        _ => format!("{}", e)
+    }
+}
+
+// Concatenate two values, as a string
+fn str(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let s = format!("{}{}", to_string(&args[0]), to_string(&args[1]));
+    Ok(Rc::new(Expr::String(s)))
+}
+
+// Display the value of the expression on the current output port
+fn print(c:&Context, args:&[Rc<Expr>]) -> Value {
+    ports::write_output(c, &to_string(&args[0]))?;
+    Ok(Rc::new(Expr::Nil))
+}
+
+fn print_debug(_:&Context, args:&[Rc<Expr>]) -> Value {
+    println!("{:?}", args[0]);
+    Ok(Rc::new(Expr::Nil))
+}
+
+// Call a function with a list of arguments, which are not evaluated again
+fn apply(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let mut xs = vec!();
+    let mut l = args[1].clone();
+    loop {
+        let next = match *l {
+            Expr::Nil => break,
+            Expr::Cons(ref car, ref cdr) => {
+                xs.push(car.clone());
+                cdr.clone()
+            },
+            _ => return Err(bad_type("_apply", &args[1]))
+        };
+        l = next;
+    }
+    c.apply(args[0].clone(), &xs)
+}
+
+// (gensym [prefix]): a new identifier, named #:prefixN (#:gN by default).
+// Neither the reader nor string->symbol can produce a name starting with
+// "#:", so it is different from all the other identifiers.
+fn gensym(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let prefix = match args.first().map(|e| &**e) {
+        None => "g",
+        Some(Expr::String(s)) => s,
+        Some(_) => return Err(bad_type("gensym", &args[0]))
+    };
+    Ok(Rc::new(Expr::Ident(format!("#:{}{}", prefix, c.fresh_id()))))
+}
+
+type TypeCheck = fn(&Expr) -> bool;
+
+const TYPE_CHECKS:&[(&str, TypeCheck)] = &[
+    ("_nil?", |e| matches!(*e, Expr::Nil)),
+    ("_list?", |e| matches!(*e, Expr::Cons(_,_))),
+    ("_boolean?", |e| matches!(*e, Expr::Bool(_))),
+    ("_lambda?", |e| matches!(*e, Expr::Lambda(_,_,_,_))),
+    ("_primitive?", |e| matches!(*e, Expr::Primitive(_))),
+    ("_macro?", |e| matches!(*e, Expr::Macro(_,_,_) | Expr::SyntaxRules(_,_))),
+    ("_integer?", |e| matches!(*e, Expr::Integer(_) | Expr::BigInt(_))),
+    ("number?", number::is_number),
+    ("exact?", number::is_exact),
+    ("inexact?", |e| matches!(*e, Expr::Float(_))),
+    ("rational?", |e| match *e {
+        Expr::Float(x) => x.is_finite(),
+        _ => number::is_exact(e)
+    }),
+    ("_float?", |e| matches!(*e, Expr::Float(_))),
+    ("_ident?", |e| matches!(*e, Expr::Ident(_))),
+    ("_string?", |e| matches!(*e, Expr::String(_))),
+    ("char?", |e| matches!(*e, Expr::Char(_))),
+    ("vector?", |e| matches!(*e, Expr::Vector(_))),
+    ("hash-table?", |e| matches!(*e, Expr::HashTable(_))),
+    ("port?", |e| matches!(*e, Expr::Port(_))),
+    ("input-port?", |e| matches!(*e, Expr::Port(ref p) if p.borrow().is_input())),
+    ("output-port?", |e| matches!(*e, Expr::Port(ref p) if p.borrow().is_output())),
+    ("eof-object?", |e| matches!(*e, Expr::Eof)),
+    ("_quote?", |e| matches!(*e, Expr::Quote(_))),
+    ("_quasiquote?", |e| matches!(*e, Expr::Quasiquote(_))),
+    ("_unquote?", |e| matches!(*e, Expr::Unquote(_))),
+    ("_unquote-splicing?", |e| matches!(*e, Expr::UnquoteSplicing(_)))];
+
+/// Defines all builtins in the global environment of the context
+pub fn register(c:&Context) {
+    c.define_primitive("_+", Arity::Exactly(2), plus);
+    c.define_primitive("_-", Arity::Exactly(2), sub);
+    c.define_primitive("_*", Arity::Exactly(2), mul);
+    c.define_primitive("_/", Arity::Exactly(2), div);
+    c.define_primitive("_=", Arity::Exactly(2), equal);
+    c.define_primitive("_<", Arity::Exactly(2), lt);
+    c.define_primitive("_>", Arity::Exactly(2), gt);
+    c.define_primitive("quotient", Arity::Exactly(2), quotient);
+    c.define_primitive("remainder", Arity::Exactly(2), remainder);
+    c.define_primitive("modulo", Arity::Exactly(2), modulo);
+    c.define_primitive("numerator", Arity::Exactly(1), numerator);
+    c.define_primitive("denominator", Arity::Exactly(1), denominator);
+    c.define_primitive("exact->inexact", Arity::Exactly(1), exact_to_inexact);
+    c.define_primitive("inexact->exact", Arity::Exactly(1), inexact_to_exact);
+    c.define_primitive("inexact", Arity::Exactly(1), exact_to_inexact);
+    c.define_primitive("exact", Arity::Exactly(1), inexact_to_exact);
+    c.define_primitive("_car", Arity::Exactly(1), car);
+    c.define_primitive("_cdr", Arity::Exactly(1), cdr);
+    c.define_primitive("_cons", Arity::Exactly(2), cons);
+    c.define_primitive("_str", Arity::Exactly(2), str);
+    c.define_primitive("_print", Arity::Exactly(1), print);
+    c.define_primitive("_apply", Arity::Exactly(2), apply);
+    c.define_primitive("call-with-current-continuation", Arity::Exactly(1),
+                       |c, args| c.call_cc(args[0].clone()));
+    c.define_primitive("call/cc", Arity::Exactly(1), |c, args| c.call_cc(args[0].clone()));
+    c.define_primitive("dynamic-wind", Arity::Exactly(3),
+                       |c, args| c.dynamic_wind(args[0].clone(), args[1].clone(), args[2].clone()));
+    c.define_primitive("load", Arity::Exactly(1), |c, args| c.load(&args[0]));
+    c.define_primitive("gensym", Arity::Range(0, 1), gensym);
+    c.define_primitive("print-debug", Arity::Exactly(1), print_debug);
+    c.define_primitive("macroexpand-1", Arity::Exactly(1), |c, args| c.macroexpand_1(&args[0]));
+    c.define_primitive("macroexpand", Arity::Exactly(1), |c, args| c.macroexpand(&args[0]));
+    for &(name, check) in TYPE_CHECKS {
+        c.define_primitive(name, Arity::Exactly(1), move |_, args| Ok(boolean(check(&args[0]))));
+    }
+    chars::register(c);
+    strings::register(c);
+    vectors::register(c);
+    hash_tables::register(c);
+    exceptions::register(c);
+    ports::register(c);
+}
@@ -0,0 +1,176 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Input and output through ports. The procedures reading or writing take
+//! the port as an optional last argument, the current input or output
+//! port being used without it.
+
+use expr::Expr;
+use eval::Context;
+use error::EvalError;
+use primitive::Arity;
+use port::Port;
+use super::{Value, bad_type};
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path::Path;
+
+fn new_port(p:Port) -> Value {
+    Ok(Rc::new(Expr::Port(RefCell::new(p))))
+}
+
+fn get_string<'a>(name:&str, e:&'a Rc<Expr>) -> Result<&'a str, EvalError> {
+    match **e {
+        Expr::String(ref s) => Ok(s),
+        _ => Err(bad_type(name, e))
+    }
+}
+
+// The open port given as argument i, or the current input or output one
+fn get_port(c:&Context, name:&str, args:&[Rc<Expr>], i:usize, input:bool) -> Result<Rc<Expr>, EvalError> {
+    let port = match args.get(i) {
+        Some(port) => port.clone(),
+        None if input => c.shared.input_port.borrow().clone(),
+        None => c.shared.output_port.borrow().clone()
+    };
+    let ok = match *port {
+        Expr::Port(ref p) => {
+            let p = p.borrow();
+            p.is_open() && p.is_input() == input
+        },
+        _ => false
+    };
+    if ok {Ok(port)} else {Err(bad_type(name, &port))}
+}
+
+// Calls f on the port, an I/O error being a file error about it
+fn with_port<T, F>(port:&Rc<Expr>, f:F) -> Result<T, EvalError>
+    where F: FnOnce(&mut Port) -> std::io::Result<T> {
+    match **port {
+        Expr::Port(ref p) => f(&mut p.borrow_mut())
+            .map_err(|e| EvalError::File(e.to_string(), port.clone())),
+        _ => Err(bad_type("port", port))
+    }
+}
+
+/// Writes s on the current output port
+pub fn write_output(c:&Context, s:&str) -> Result<(), EvalError> {
+    let port = get_port(c, "_print", &[], 0, false)?;
+    with_port(&port, |p| p.write_str(s))
+}
+
+fn open_input_file(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let name = get_string("open-input-file", &args[0])?;
+    let p = Port::open_input_file(Path::new(name))
+        .map_err(|e| EvalError::File(e.to_string(), args[0].clone()))?;
+    new_port(p)
+}
+
+fn open_output_file(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let name = get_string("open-output-file", &args[0])?;
+    let p = Port::open_output_file(Path::new(name))
+        .map_err(|e| EvalError::File(e.to_string(), args[0].clone()))?;
+    new_port(p)
+}
+
+fn open_input_string(_:&Context, args:&[Rc<Expr>]) -> Value {
+    new_port(Port::from_string(get_string("open-input-string", &args[0])?))
+}
+
+fn get_output_string(_:&Context, args:&[Rc<Expr>]) -> Value {
+    if let Expr::Port(ref p) = *args[0] {
+        if let Port::String(ref s) = *p.borrow() {
+            return Ok(Rc::new(Expr::String(s.clone())));
+        }
+    }
+    Err(bad_type("get-output-string", &args[0]))
+}
+
+fn char_or_eof(c:Option<char>) -> Rc<Expr> {
+    Rc::new(c.map_or(Expr::Eof, Expr::Char))
+}
+
+fn read_char(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let port = get_port(c, "read-char", args, 0, true)?;
+    with_port(&port, |p| p.read_char()).map(char_or_eof)
+}
+
+fn peek_char(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let port = get_port(c, "peek-char", args, 0, true)?;
+    with_port(&port, |p| p.peek_char()).map(char_or_eof)
+}
+
+fn read_line(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let port = get_port(c, "read-line", args, 0, true)?;
+    let line = with_port(&port, |p| p.read_line())?;
+    Ok(Rc::new(line.map_or(Expr::Eof, Expr::String)))
+}
+
+fn write_string(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let s = get_string("write-string", &args[0])?;
+    let port = get_port(c, "write-string", args, 1, false)?;
+    with_port(&port, |p| p.write_str(s))?;
+    Ok(Rc::new(Expr::Nil))
+}
+
+fn write_char(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let ch = match *args[0] {
+        Expr::Char(ch) => ch,
+        _ => return Err(bad_type("write-char", &args[0]))
+    };
+    let port = get_port(c, "write-char", args, 1, false)?;
+    with_port(&port, |p| p.write_str(ch.encode_utf8(&mut [0; 4])))?;
+    Ok(Rc::new(Expr::Nil))
+}
+
+fn newline(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let port = get_port(c, "newline", args, 0, false)?;
+    with_port(&port, |p| p.write_str("\n"))?;
+    Ok(Rc::new(Expr::Nil))
+}
+
+// Closing a closed port does nothing
+fn close_port(_:&Context, args:&[Rc<Expr>]) -> Value {
+    with_port(&args[0], |p| p.close())?;
+    Ok(Rc::new(Expr::Nil))
+}
+
+// (with-output-to-string thunk): calls thunk with a string port as the
+// current output port, and returns what it wrote
+fn with_output_to_string(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let port = Rc::new(Expr::Port(RefCell::new(Port::String(String::new()))));
+    let previous = c.shared.output_port.replace(port.clone());
+    let res = c.apply(args[0].clone(), &[]);
+    *c.shared.output_port.borrow_mut() = previous;
+    res?;
+    get_output_string(c, &[port])
+}
+
+/// Defines the procedures on ports
+pub fn register(c:&Context) {
+    c.define_primitive("open-input-file", Arity::Exactly(1), open_input_file);
+    c.define_primitive("open-output-file", Arity::Exactly(1), open_output_file);
+    c.define_primitive("open-input-string", Arity::Exactly(1), open_input_string);
+    c.define_primitive("open-output-string", Arity::Exactly(0),
+                       |_, _| new_port(Port::String(String::new())));
+    c.define_primitive("get-output-string", Arity::Exactly(1), get_output_string);
+    c.define_primitive("read-char", Arity::Range(0, 1), read_char);
+    c.define_primitive("peek-char", Arity::Range(0, 1), peek_char);
+    c.define_primitive("read-line", Arity::Range(0, 1), read_line);
+    c.define_primitive("write-string", Arity::Range(1, 2), write_string);
+    c.define_primitive("write-char", Arity::Range(1, 2), write_char);
+    c.define_primitive("newline", Arity::Range(0, 1), newline);
+    c.define_primitive("close-port", Arity::Exactly(1), close_port);
+    c.define_primitive("close-input-port", Arity::Exactly(1), close_port);
+    c.define_primitive("close-output-port", Arity::Exactly(1), close_port);
+    c.define_primitive("current-input-port", Arity::Exactly(0), |c, _| Ok(c.shared.input_port.borrow().clone()));
+    c.define_primitive("current-output-port", Arity::Exactly(0), |c, _| Ok(c.shared.output_port.borrow().clone()));
+    c.define_primitive("with-output-to-string", Arity::Exactly(1), with_output_to_string);
+    c.define_primitive("eof-object", Arity::Exactly(0), |_, _| Ok(Rc::new(Expr::Eof)));
+}
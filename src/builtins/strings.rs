@@ -0,0 +1,247 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Procedures on strings. Indices are counted in characters, not bytes.
+
+use expr::Expr;
+use eval::Context;
+use error::EvalError;
+use primitive::Arity;
+use number;
+use super::{Value, bad_type, boolean};
+
+use num_bigint::BigInt;
+
+use std::rc::Rc;
+use std::cmp::Ordering;
+
+fn get_string<'a>(name:&str, e:&'a Rc<Expr>) -> Result<&'a str, EvalError> {
+    match **e {
+        Expr::String(ref s) => Ok(s),
+        _ => Err(bad_type(name, e))
+    }
+}
+
+fn get_index(name:&str, e:&Rc<Expr>) -> Result<usize, EvalError> {
+    match **e {
+        Expr::Integer(x) if x >= 0 => Ok(x as usize),
+        _ => Err(bad_type(name, e))
+    }
+}
+
+fn string(s:String) -> Value {
+    Ok(Rc::new(Expr::String(s)))
+}
+
+// Byte offset of the character at index k (k can be the length of s)
+fn byte_offset(name:&str, s:&str, k:&Rc<Expr>) -> Result<usize, EvalError> {
+    let i = get_index(name, k)?;
+    match s.char_indices().map(|(b, _)| b).chain(Some(s.len())).nth(i) {
+        Some(b) => Ok(b),
+        None => Err(EvalError::OutOfRange(name.to_string(), k.clone()))
+    }
+}
+
+fn string_length(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let s = get_string("string-length", &args[0])?;
+    Ok(Rc::new(Expr::Integer(s.chars().count() as i64)))
+}
+
+fn string_ref(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let s = get_string("string-ref", &args[0])?;
+    let b = byte_offset("string-ref", s, &args[1])?;
+    match s[b..].chars().next() {
+        Some(c) => Ok(Rc::new(Expr::Char(c))),
+        None => Err(EvalError::OutOfRange("string-ref".to_string(), args[1].clone()))
+    }
+}
+
+// (substring s start [end])
+fn substring(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let s = get_string("substring", &args[0])?;
+    let start = byte_offset("substring", s, &args[1])?;
+    let end = match args.get(2) {
+        Some(end) => byte_offset("substring", s, end)?,
+        None => s.len()
+    };
+    if start > end {
+        return Err(EvalError::OutOfRange("substring".to_string(), args[1].clone()));
+    }
+    string(s[start..end].to_string())
+}
+
+fn string_append(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let mut res = String::new();
+    for arg in args {
+        res.push_str(get_string("string-append", arg)?);
+    }
+    string(res)
+}
+
+fn string_upcase(_:&Context, args:&[Rc<Expr>]) -> Value {
+    string(get_string("string-upcase", &args[0])?.to_uppercase())
+}
+
+fn string_downcase(_:&Context, args:&[Rc<Expr>]) -> Value {
+    string(get_string("string-downcase", &args[0])?.to_lowercase())
+}
+
+fn get_radix(name:&str, args:&[Rc<Expr>]) -> Result<u32, EvalError> {
+    match args.get(1).map(|e| (e, &**e)) {
+        None => Ok(10),
+        Some((_, &Expr::Integer(r))) if r == 2 || r == 8 || r == 10 || r == 16 => Ok(r as u32),
+        Some((e, _)) => Err(bad_type(name, e))
+    }
+}
+
+// (string->number s [radix]): #f if s is not a number
+fn string_to_number(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let s = get_string("string->number", &args[0])?;
+    let prefix = match get_radix("string->number", args)? {
+        2 => "#b",
+        8 => "#o",
+        16 => "#x",
+        _ => ""
+    };
+    match number::parse(&format!("{}{}", prefix, s)) {
+        Some(n) => Ok(Rc::new(n)),
+        None => Ok(boolean(false))
+    }
+}
+
+// (number->string n [radix]); the radix is only used for exact numbers
+fn number_to_string(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let radix = get_radix("number->string", args)?;
+    match *args[0] {
+        Expr::Integer(x) => string(BigInt::from(x).to_str_radix(radix)),
+        Expr::BigInt(ref x) => string(x.to_str_radix(radix)),
+        Expr::Rational(ref x) =>
+            string(format!("{}/{}", x.numer().to_str_radix(radix), x.denom().to_str_radix(radix))),
+        Expr::Float(x) => string(number::format_float(x)),
+        _ => Err(bad_type("number->string", &args[0]))
+    }
+}
+
+// The names starting with "#:" are those of the identifiers made by
+// gensym, which can't be created any other way
+fn string_to_symbol(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let s = get_string("string->symbol", &args[0])?;
+    if s.starts_with("#:") {
+        return Err(bad_type("string->symbol", &args[0]));
+    }
+    Ok(Rc::new(Expr::Ident(s.to_string())))
+}
+
+fn symbol_to_string(_:&Context, args:&[Rc<Expr>]) -> Value {
+    match *args[0] {
+        Expr::Ident(ref s) => string(s.clone()),
+        _ => Err(bad_type("symbol->string", &args[0]))
+    }
+}
+
+fn string_to_list(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let s = get_string("string->list", &args[0])?;
+    let mut res = Rc::new(Expr::Nil);
+    for c in s.chars().rev() {
+        res = Rc::new(Expr::Cons(Rc::new(Expr::Char(c)), res));
+    }
+    Ok(res)
+}
+
+fn list_to_string(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let mut res = String::new();
+    let mut l = args[0].clone();
+    loop {
+        let next = match *l {
+            Expr::Nil => return string(res),
+            Expr::Cons(ref car, ref cdr) => match **car {
+                Expr::Char(c) => {
+                    res.push(c);
+                    cdr.clone()
+                },
+                _ => return Err(bad_type("list->string", car))
+            },
+            _ => return Err(bad_type("list->string", &args[0]))
+        };
+        l = next;
+    }
+}
+
+// (string-split s separator): the separator is a character or a string
+fn string_split(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let s = get_string("string-split", &args[0])?;
+    let parts:Vec<&str> = match *args[1] {
+        Expr::Char(c) => s.split(c).collect(),
+        Expr::String(ref sep) if !sep.is_empty() => s.split(sep.as_str()).collect(),
+        _ => return Err(bad_type("string-split", &args[1]))
+    };
+    let mut res = Rc::new(Expr::Nil);
+    for part in parts.into_iter().rev() {
+        res = Rc::new(Expr::Cons(Rc::new(Expr::String(part.to_string())), res));
+    }
+    Ok(res)
+}
+
+// (string-index s pred): index of the first character equal to pred, or
+// satisfying it if it is a function; #f if there is none
+fn string_index(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let s = get_string("string-index", &args[0])?;
+    for (i, ch) in s.chars().enumerate() {
+        let found = match *args[1] {
+            Expr::Char(x) => x == ch,
+            _ => {
+                let res = c.apply(args[1].clone(), &[Rc::new(Expr::Char(ch))])?;
+                c.is_true(&res)
+            }
+        };
+        if found {
+            return Ok(Rc::new(Expr::Integer(i as i64)));
+        }
+    }
+    Ok(boolean(false))
+}
+
+// Compare each string with the next one
+fn string_compare(name:&str, args:&[Rc<Expr>], f:fn(Ordering) -> bool) -> Value {
+    let mut prev = get_string(name, &args[0])?;
+    let mut res = true;
+    for arg in &args[1..] {
+        let s = get_string(name, arg)?;
+        res = res && f(prev.cmp(s));
+        prev = s;
+    }
+    Ok(boolean(res))
+}
+
+/// Defines the procedures on strings
+pub fn register(c:&Context) {
+    c.define_primitive("string-length", Arity::Exactly(1), string_length);
+    c.define_primitive("string-ref", Arity::Exactly(2), string_ref);
+    c.define_primitive("substring", Arity::Range(2, 3), substring);
+    c.define_primitive("string-append", Arity::AtLeast(0), string_append);
+    c.define_primitive("string-upcase", Arity::Exactly(1), string_upcase);
+    c.define_primitive("string-downcase", Arity::Exactly(1), string_downcase);
+    c.define_primitive("string->number", Arity::Range(1, 2), string_to_number);
+    c.define_primitive("number->string", Arity::Range(1, 2), number_to_string);
+    c.define_primitive("string->symbol", Arity::Exactly(1), string_to_symbol);
+    c.define_primitive("symbol->string", Arity::Exactly(1), symbol_to_string);
+    c.define_primitive("string->list", Arity::Exactly(1), string_to_list);
+    c.define_primitive("list->string", Arity::Exactly(1), list_to_string);
+    c.define_primitive("string-split", Arity::Exactly(2), string_split);
+    c.define_primitive("string-index", Arity::Exactly(2), string_index);
+    c.define_primitive("string=?", Arity::AtLeast(1),
+                       |_, args| string_compare("string=?", args, |o| o == Ordering::Equal));
+    c.define_primitive("string<?", Arity::AtLeast(1),
+                       |_, args| string_compare("string<?", args, |o| o == Ordering::Less));
+    c.define_primitive("string>?", Arity::AtLeast(1),
+                       |_, args| string_compare("string>?", args, |o| o == Ordering::Greater));
+    c.define_primitive("string<=?", Arity::AtLeast(1),
+                       |_, args| string_compare("string<=?", args, |o| o != Ordering::Greater));
+    c.define_primitive("string>=?", Arity::AtLeast(1),
+                       |_, args| string_compare("string>=?", args, |o| o != Ordering::Less));
+}
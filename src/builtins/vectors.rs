@@ -0,0 +1,133 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Procedures on vectors. A vector is modified in place by `vector-set!`
+//! and `vector-fill!`, so the change is seen through all its references.
+
+use expr::Expr;
+use eval::Context;
+use error::EvalError;
+use primitive::Arity;
+use super::{Value, bad_type, boolean};
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+fn get_vector<'a>(name:&str, e:&'a Rc<Expr>) -> Result<&'a RefCell<Vec<Rc<Expr>>>, EvalError> {
+    match **e {
+        Expr::Vector(ref v) => Ok(v),
+        _ => Err(bad_type(name, e))
+    }
+}
+
+fn get_index(name:&str, e:&Rc<Expr>, len:usize) -> Result<usize, EvalError> {
+    match **e {
+        Expr::Integer(x) if x >= 0 && (x as usize) < len => Ok(x as usize),
+        Expr::Integer(_) => Err(EvalError::OutOfRange(name.to_string(), e.clone())),
+        _ => Err(bad_type(name, e))
+    }
+}
+
+fn vector(elems:Vec<Rc<Expr>>) -> Value {
+    Ok(Rc::new(Expr::Vector(RefCell::new(elems))))
+}
+
+// (make-vector k [fill]): the elements are #f if fill is not given
+fn make_vector(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let k = match *args[0] {
+        Expr::Integer(k) if k >= 0 => k as usize,
+        _ => return Err(bad_type("make-vector", &args[0]))
+    };
+    let fill = match args.get(1) {
+        Some(e) => e.clone(),
+        None => boolean(false)
+    };
+    // a size too large for the memory is out of range rather than a panic
+    let mut v = Vec::new();
+    if v.try_reserve_exact(k).is_err() {
+        return Err(EvalError::OutOfRange("make-vector".to_string(), args[0].clone()));
+    }
+    v.resize(k, fill);
+    vector(v)
+}
+
+fn vector_ref(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let v = get_vector("vector-ref", &args[0])?.borrow();
+    let i = get_index("vector-ref", &args[1], v.len())?;
+    Ok(v[i].clone())
+}
+
+// Returns the new value of the element
+fn vector_set(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let mut v = get_vector("vector-set!", &args[0])?.borrow_mut();
+    let i = get_index("vector-set!", &args[1], v.len())?;
+    v[i] = args[2].clone();
+    Ok(args[2].clone())
+}
+
+fn vector_length(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let v = get_vector("vector-length", &args[0])?.borrow();
+    Ok(Rc::new(Expr::Integer(v.len() as i64)))
+}
+
+fn vector_to_list(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let v = get_vector("vector->list", &args[0])?.borrow();
+    let mut res = Rc::new(Expr::Nil);
+    for e in v.iter().rev() {
+        res = Rc::new(Expr::Cons(e.clone(), res));
+    }
+    Ok(res)
+}
+
+fn list_to_vector(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let mut res = vec!();
+    let mut l = args[0].clone();
+    loop {
+        let next = match *l {
+            Expr::Nil => return vector(res),
+            Expr::Cons(ref car, ref cdr) => {
+                res.push(car.clone());
+                cdr.clone()
+            },
+            _ => return Err(bad_type("list->vector", &args[0]))
+        };
+        l = next;
+    }
+}
+
+// (vector-map f v): the elements are copied first, so f can modify v
+fn vector_map(c:&Context, args:&[Rc<Expr>]) -> Value {
+    let elems = get_vector("vector-map", &args[1])?.borrow().clone();
+    let mut res = Vec::with_capacity(elems.len());
+    for e in elems {
+        res.push(c.apply(args[0].clone(), &[e])?);
+    }
+    vector(res)
+}
+
+// Returns the vector
+fn vector_fill(_:&Context, args:&[Rc<Expr>]) -> Value {
+    let mut v = get_vector("vector-fill!", &args[0])?.borrow_mut();
+    for e in v.iter_mut() {
+        *e = args[1].clone();
+    }
+    Ok(args[0].clone())
+}
+
+/// Defines the procedures on vectors
+pub fn register(c:&Context) {
+    c.define_primitive("vector", Arity::AtLeast(0), |_, args| vector(args.to_vec()));
+    c.define_primitive("make-vector", Arity::Range(1, 2), make_vector);
+    c.define_primitive("vector-ref", Arity::Exactly(2), vector_ref);
+    c.define_primitive("vector-set!", Arity::Exactly(3), vector_set);
+    c.define_primitive("vector-length", Arity::Exactly(1), vector_length);
+    c.define_primitive("vector->list", Arity::Exactly(1), vector_to_list);
+    c.define_primitive("list->vector", Arity::Exactly(1), list_to_vector);
+    c.define_primitive("vector-map", Arity::Exactly(2), vector_map);
+    c.define_primitive("vector-fill!", Arity::Exactly(2), vector_fill);
+}
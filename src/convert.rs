@@ -0,0 +1,160 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Conversions between Scheme values and Rust values.
+
+use expr::Expr;
+use error::EvalError;
+
+use number;
+
+use num_bigint::BigInt;
+
+use std::rc::Rc;
+
+impl From<bool> for Expr {
+    fn from(b:bool) -> Expr {
+        Expr::Bool(b)
+    }
+}
+
+impl From<i64> for Expr {
+    fn from(x:i64) -> Expr {
+        Expr::Integer(x)
+    }
+}
+
+/// A big integer is converted to an Integer if it fits in an i64
+impl From<BigInt> for Expr {
+    fn from(x:BigInt) -> Expr {
+        number::from_bigint(x)
+    }
+}
+
+impl From<f64> for Expr {
+    fn from(x:f64) -> Expr {
+        Expr::Float(x)
+    }
+}
+
+impl From<char> for Expr {
+    fn from(c:char) -> Expr {
+        Expr::Char(c)
+    }
+}
+
+impl From<String> for Expr {
+    fn from(s:String) -> Expr {
+        Expr::String(s)
+    }
+}
+
+impl<'a> From<&'a str> for Expr {
+    fn from(s:&'a str) -> Expr {
+        Expr::String(s.to_string())
+    }
+}
+
+/// A vector is converted to a list
+impl<T:Into<Expr>> From<Vec<T>> for Expr {
+    fn from(v:Vec<T>) -> Expr {
+        let mut res = Expr::Nil;
+        for x in v.into_iter().rev() {
+            res = Expr::Cons(Rc::new(x.into()), Rc::new(res));
+        }
+        res
+    }
+}
+
+/// Conversion of a Scheme value to a Rust value
+pub trait FromExpr: Sized {
+    /// Returns the converted value, or a TypeMismatch error if the
+    /// expression doesn't have the right type
+    fn from_expr(e:&Rc<Expr>) -> Result<Self, EvalError>;
+}
+
+impl FromExpr for Rc<Expr> {
+    fn from_expr(e:&Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        Ok(e.clone())
+    }
+}
+
+impl FromExpr for bool {
+    fn from_expr(e:&Rc<Expr>) -> Result<bool, EvalError> {
+        match **e {
+            Expr::Bool(b) => Ok(b),
+            _ => Err(EvalError::TypeMismatch("bool".to_string(), e.clone()))
+        }
+    }
+}
+
+impl FromExpr for i64 {
+    fn from_expr(e:&Rc<Expr>) -> Result<i64, EvalError> {
+        match **e {
+            Expr::Integer(x) => Ok(x),
+            _ => Err(EvalError::TypeMismatch("i64".to_string(), e.clone()))
+        }
+    }
+}
+
+impl FromExpr for BigInt {
+    fn from_expr(e:&Rc<Expr>) -> Result<BigInt, EvalError> {
+        match **e {
+            Expr::Integer(x) => Ok(BigInt::from(x)),
+            Expr::BigInt(ref x) => Ok(x.clone()),
+            _ => Err(EvalError::TypeMismatch("BigInt".to_string(), e.clone()))
+        }
+    }
+}
+
+/// Exact numbers are converted to floats too (approximately)
+impl FromExpr for f64 {
+    fn from_expr(e:&Rc<Expr>) -> Result<f64, EvalError> {
+        match number::to_f64(e) {
+            Some(x) => Ok(x),
+            None => Err(EvalError::TypeMismatch("f64".to_string(), e.clone()))
+        }
+    }
+}
+
+impl FromExpr for char {
+    fn from_expr(e:&Rc<Expr>) -> Result<char, EvalError> {
+        match **e {
+            Expr::Char(c) => Ok(c),
+            _ => Err(EvalError::TypeMismatch("char".to_string(), e.clone()))
+        }
+    }
+}
+
+impl FromExpr for String {
+    fn from_expr(e:&Rc<Expr>) -> Result<String, EvalError> {
+        match **e {
+            Expr::String(ref s) => Ok(s.clone()),
+            _ => Err(EvalError::TypeMismatch("String".to_string(), e.clone()))
+        }
+    }
+}
+
+/// A proper list is converted to a vector
+impl<T:FromExpr> FromExpr for Vec<T> {
+    fn from_expr(e:&Rc<Expr>) -> Result<Vec<T>, EvalError> {
+        let mut res = vec!();
+        let mut l = e.clone();
+        loop {
+            let next = match *l {
+                Expr::Nil => return Ok(res),
+                Expr::Cons(ref car, ref cdr) => {
+                    res.push(T::from_expr(car)?);
+                    cdr.clone()
+                },
+                _ => return Err(EvalError::TypeMismatch("Vec".to_string(), e.clone()))
+            };
+            l = next;
+        }
+    }
+}
@@ -0,0 +1,136 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+use expr::Expr;
+
+use std::fmt;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// A frame of the environment: the bindings of one scope (a function
+/// call, or the global scope), and the frame of the enclosing scope.
+///
+/// Frames are reference-counted, so a closure keeps the frame where it was
+/// defined alive and sees later changes to it.
+pub struct Env {
+    vars: RefCell<HashMap<String,Rc<Expr>>>,
+    // identifiers standing for a variable of another environment (its name
+    // there, and the frame to look it up from), e.g. the imports of a
+    // library
+    aliases: RefCell<HashMap<String,(String, Rc<Env>)>>,
+    parent: Option<Rc<Env>>
+}
+
+impl Env {
+    /// Creates a frame with no enclosing scope
+    pub fn new() -> Rc<Env> {
+        Rc::new(Env {
+            vars: RefCell::new(HashMap::new()),
+            aliases: RefCell::new(HashMap::new()),
+            parent: None
+        })
+    }
+
+    /// Creates an empty frame extending parent
+    pub fn extend(parent:&Rc<Env>) -> Rc<Env> {
+        Rc::new(Env {
+            vars: RefCell::new(HashMap::new()),
+            aliases: RefCell::new(HashMap::new()),
+            parent: Some(parent.clone())
+        })
+    }
+
+    /// Looks for the value of an identifier in this frame, then in the
+    /// enclosing ones
+    pub fn lookup(&self, ident:&str) -> Option<Rc<Expr>> {
+        let mut env = self;
+        loop {
+            if let Some(e) = env.vars.borrow().get(ident) {
+                return Some(e.clone());
+            }
+            if let Some((name, target)) = env.alias(ident) {
+                return target.lookup(&name);
+            }
+            match env.parent {
+                None => return None,
+                Some(ref parent) => env = parent
+            }
+        }
+    }
+
+    /// Binds an identifier in this frame, shadowing a binding of an
+    /// enclosing frame
+    pub fn define(&self, ident:String, expr:Rc<Expr>) {
+        self.aliases.borrow_mut().remove(&ident);
+        self.vars.borrow_mut().insert(ident, expr);
+    }
+
+    /// Binds an identifier in this frame to the variable named target in
+    /// another environment: looking it up or changing it looks up or
+    /// changes that variable
+    pub fn define_alias(&self, ident:String, target:String, env:Rc<Env>) {
+        self.vars.borrow_mut().remove(&ident);
+        self.aliases.borrow_mut().insert(ident, (target, env));
+    }
+
+    // The variable an identifier stands for, if it is an alias in this
+    // very frame
+    fn alias(&self, ident:&str) -> Option<(String, Rc<Env>)> {
+        self.aliases.borrow().get(ident).cloned()
+    }
+
+    /// Changes the value of an identifier in the innermost frame where it
+    /// is bound. Returns false if it is not bound at all.
+    pub fn set(&self, ident:&str, expr:Rc<Expr>) -> bool {
+        let mut env = self;
+        loop {
+            if let Some(e) = env.vars.borrow_mut().get_mut(ident) {
+                *e = expr;
+                return true;
+            }
+            if let Some((name, target)) = env.alias(ident) {
+                return target.set(&name, expr);
+            }
+            match env.parent {
+                None => return false,
+                Some(ref parent) => env = parent
+            }
+        }
+    }
+
+    /// Returns true if the identifier is bound in this very frame
+    pub fn contains(&self, ident:&str) -> bool {
+        self.vars.borrow().contains_key(ident) || self.aliases.borrow().contains_key(ident)
+    }
+}
+
+// Only the names are displayed: values can contain closures referencing
+// this very frame
+impl Debug for Env {
+    fn fmt(&self, formatter:&mut Formatter) -> fmt::Result {
+        let vars = self.vars.borrow();
+        let aliases = self.aliases.borrow();
+        let mut names:Vec<&String> = vars.keys().chain(aliases.keys()).collect();
+        names.sort();
+        write!(formatter, "Env {:?}", names)?;
+        match self.parent {
+            Some(ref parent) => write!(formatter, " -> {:?}", parent),
+            None => Ok(())
+        }
+    }
+}
+
+// Two frames are only equal if they are the same frame
+impl PartialEq for Env {
+    fn eq(&self, other:&Env) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
@@ -0,0 +1,161 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+use expr::Expr;
+use source::Span;
+
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::rc::Rc;
+
+/// Errors that can occur while evaluating an expression.
+///
+/// Every variant carries the offending expression, so a caller can
+/// tell what went wrong and where, except syntax errors, which happen
+/// before there is any expression.
+#[derive(Debug,Clone,PartialEq)]
+pub enum EvalError {
+    /// An identifier is bound neither in the local nor in the global environment
+    UnboundVariable(Rc<Expr>),
+    /// Wrong number of arguments given to a function, macro or builtin
+    /// (name of the callee, call form)
+    Arity(String, Rc<Expr>),
+    /// An argument doesn't have the type expected by an operator
+    /// (name of the operator, offending value)
+    TypeMismatch(String, Rc<Expr>),
+    /// A special form (if, def, lambda...) is ill-formed
+    /// (name of the form, form)
+    BadSpecialForm(String, Rc<Expr>),
+    /// Integer division by zero
+    DivisionByZero(Rc<Expr>),
+    /// An index is out of the bounds of a string or a vector
+    /// (name of the operator, index)
+    OutOfRange(String, Rc<Expr>),
+    /// A key is not in a hash table (name of the operator, key)
+    KeyNotFound(String, Rc<Expr>),
+    /// An error signaled by the program with error (message, list of
+    /// irritants)
+    Error(String, Rc<Expr>),
+    /// An object raised by raise (or an error object), that no exception
+    /// handler took care of
+    Raised(Rc<Expr>),
+    /// A file can't be loaded, included or used (reason, name of the file,
+    /// or port)
+    File(String, Rc<Expr>),
+    /// A library can't be imported (reason, import set)
+    Library(String, Rc<Expr>),
+    /// A continuation was called: the evaluation unwinds up to the call/cc
+    /// that made it (number of the continuation, value given to it). It
+    /// only reaches the caller of eval if that call/cc already returned.
+    Escape(usize, Rc<Expr>),
+    /// Attempt to bind a reserved keyword
+    ReservedIdent(Rc<Expr>),
+    /// The first element of an evaluated list is not something that can be called
+    NotAFunction(Rc<Expr>),
+    /// The source code can't be read (reason, position of the offending
+    /// text)
+    Syntax(String, Span),
+    /// An error, along with the position in the source of the innermost
+    /// expression that caused it
+    Located(Span, Box<EvalError>),
+}
+
+impl EvalError {
+    /// Returns the expression that caused the error (nil for a syntax
+    /// error)
+    pub fn expr(&self) -> Rc<Expr> {
+        match *self {
+            EvalError::UnboundVariable(ref e)
+                | EvalError::Arity(_, ref e)
+                | EvalError::TypeMismatch(_, ref e)
+                | EvalError::BadSpecialForm(_, ref e)
+                | EvalError::DivisionByZero(ref e)
+                | EvalError::OutOfRange(_, ref e)
+                | EvalError::KeyNotFound(_, ref e)
+                | EvalError::Error(_, ref e)
+                | EvalError::Raised(ref e)
+                | EvalError::File(_, ref e)
+                | EvalError::Library(_, ref e)
+                | EvalError::Escape(_, ref e)
+                | EvalError::ReservedIdent(ref e)
+                | EvalError::NotAFunction(ref e) => e.clone(),
+            EvalError::Syntax(_, _) => Rc::new(Expr::Nil),
+            EvalError::Located(_, ref err) => err.expr()
+        }
+    }
+
+    /// Returns the position of the error in the source, if known. For a
+    /// raised error object, it is the position of the error it wraps.
+    pub fn span(&self) -> Option<&Span> {
+        match *self {
+            EvalError::Located(ref span, _) | EvalError::Syntax(_, ref span) => Some(span),
+            EvalError::Raised(ref e) => match **e {
+                Expr::Error(ref err) => err.span(),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    /// Returns the error without its position
+    pub fn cause(&self) -> &EvalError {
+        match *self {
+            EvalError::Located(_, ref err) => err.cause(),
+            _ => self
+        }
+    }
+}
+
+impl Display for EvalError {
+    fn fmt(&self, formatter:&mut Formatter) -> fmt::Result {
+        match *self {
+            EvalError::UnboundVariable(ref e) =>
+                write!(formatter, "variable {} not found in environment", e),
+            EvalError::Arity(ref name, ref e) =>
+                write!(formatter, "wrong number of arguments to {} in {}", name, e),
+            EvalError::TypeMismatch(ref name, ref e) =>
+                write!(formatter, "invalid type of argument for {}: {}", name, e),
+            EvalError::BadSpecialForm(ref name, ref e) =>
+                write!(formatter, "ill-formed {}: {}", name, e),
+            EvalError::DivisionByZero(ref e) =>
+                write!(formatter, "division by zero in {}", e),
+            EvalError::OutOfRange(ref name, ref e) =>
+                write!(formatter, "index out of range for {}: {}", name, e),
+            EvalError::KeyNotFound(ref name, ref e) =>
+                write!(formatter, "key not found by {}: {}", name, e),
+            EvalError::Error(ref message, ref irritants) => {
+                formatter.write_str(message)?;
+                let mut l = irritants;
+                while let Expr::Cons(ref e, ref next) = **l {
+                    write!(formatter, " {}", e)?;
+                    l = next;
+                }
+                Ok(())
+            },
+            EvalError::Raised(ref e) => match **e {
+                Expr::Error(ref err) => err.fmt(formatter),
+                _ => write!(formatter, "uncaught exception: {}", e)
+            },
+            EvalError::File(ref reason, ref e) =>
+                write!(formatter, "file {}: {}", e, reason),
+            EvalError::Library(ref reason, ref e) =>
+                write!(formatter, "can't import {}: {}", e, reason),
+            EvalError::Escape(_, ref e) =>
+                write!(formatter, "continuation called outside of its extent with {}", e),
+            EvalError::ReservedIdent(ref e) =>
+                write!(formatter, "keyword {} is reserved", e),
+            EvalError::NotAFunction(ref e) =>
+                write!(formatter, "{} is not a function or a macro", e),
+            EvalError::Syntax(ref reason, ref span) =>
+                write!(formatter, "{}: syntax error: {}", span, reason),
+            EvalError::Located(ref span, ref err) =>
+                write!(formatter, "{}: {}", span, err)
+        }
+    }
+}
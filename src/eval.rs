@@ -9,43 +9,45 @@
 
 use expr::Expr;
 use read;
+use error::EvalError;
+use source::SourceMap;
+use env::Env;
+use primitive::{Primitive, Arity};
+use syntax::SyntaxRules;
+use library::{self, Library, ImportSet};
+use port::Port;
+use builtins;
 
 use std::rc::Rc;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
 
+// Names of the special forms; builtin functions are primitives bound in
+// the global environment, and can be redefined
 const RESERVED_IDENTS:&'static[&'static str] = &[
-    "print-debug",
     "defmacro",
-    "_cons",
     "lambda",
     "eval",
     "def",
+    "define",
+    "do",
+    "begin",
+    "let",
+    "let*",
+    "letrec",
+    "letrec*",
     "if",
-    "_+",
-    "_-",
-    "_*",
-    "_/",
-    "_=",
-    "_<",
-    "_>",
-    "_car",
-    "_cdr",
-    "_&",
-    "_nil?",
-    "_lambda?",
-    "_macro?",
-    "_integer?",
-    "_float?",
-    "_ident?",
-    "_string?",
-    "_quote?",
-    "_unquote?",
-    "_quasiquote?",
-    "_list?",
-        "_str",
-    "_print"];
+    "set!",
+    "syntax-rules",
+    "define-syntax",
+    "let-syntax",
+    "letrec-syntax",
+    "guard",
+    "include",
+    "define-library",
+    "import"];
 
 fn is_reserved_ident (s: &str) -> bool {
     for i in RESERVED_IDENTS {
@@ -56,54 +58,207 @@ fn is_reserved_ident (s: &str) -> bool {
     return false;
 }
 
-// Merge two environments (= hashmaps)
-fn merge_envs (x:&mut HashMap<String,Rc<Expr>>, y:&Option<HashMap<String,Rc<Expr>>>) 
-{
-    //    let mut res = x.clone();
-    match *y {
-        None => (),
-        Some(ref h) =>  {
-            for (s,e) in h {
-                x.insert(s.clone(),e.clone());
-            }
+// Result of a single evaluation step: either the final value, or an
+// expression in tail position that remains to be evaluated. The latter
+// is evaluated by the loop in Context::eval, so tail calls don't grow the
+// Rust stack.
+enum Step {
+    Value(Context),
+    TailCall(Context)
+}
+
+// A binding of a let form: name and (unevaluated) expression
+type Binding = (String, Rc<Expr>);
+
+// The names bound locally around a form being expanded
+type Bound = HashSet<String>;
+
+fn bind<I:IntoIterator<Item=String>> (bound:&Bound, names:I) -> Bound {
+    let mut bound = bound.clone();
+    bound.extend(names);
+    bound
+}
+
+// Whether e is a form whose head is a locally bound name, and thus not the
+// use of a macro or of a special form
+fn is_shadowed (e:&Rc<Expr>, bound:&Bound) -> bool {
+    match **e {
+        Expr::Cons(ref head, _) => ident_name(head).is_some_and(|s| bound.contains(&s)),
+        _ => false
+    }
+}
+
+fn ident_name (e:&Rc<Expr>) -> Option<String> {
+    match **e {
+        Expr::Ident(ref s) => Some(s.clone()),
+        _ => None
+    }
+}
+
+fn nth (l:&Rc<Expr>, n:usize) -> Option<Rc<Expr>> {
+    list_elems(l).into_iter().nth(n)
+}
+
+fn list_elems (l:&Rc<Expr>) -> Vec<Rc<Expr>> {
+    let mut elems = vec!();
+    let mut tail = l.clone();
+    while let Expr::Cons(ref car, ref cdr) = *tail.clone() {
+        elems.push(car.clone());
+        tail = cdr.clone();
+    }
+    elems
+}
+
+// Add the names of a parameter list, proper, dotted or a single rest
+// parameter
+fn param_names (params:&Rc<Expr>, names:&mut Vec<String>) {
+    match **params {
+        Expr::Ident(ref s) => names.push(s.clone()),
+        Expr::Cons(ref param, ref rest) => {
+            names.extend(ident_name(param));
+            param_names(rest, names);
+        },
+        _ => ()
+    }
+}
+
+// Add the names defined by the forms of a body, from the index start
+fn defined_names (l:&Rc<Expr>, start:usize, names:&mut Vec<String>) {
+    for form in list_elems(l).iter().skip(start) {
+        let keyword = nth(form, 0).and_then(|k| ident_name(&k));
+        match keyword.as_deref() {
+            Some("define") | Some("def") | Some("define-syntax") | Some("defmacro") => {
+                match nth(form, 1) {
+                    Some(ref name) if matches!(**name, Expr::Cons(_, _)) =>
+                        names.extend(nth(name, 0).and_then(|n| ident_name(&n))),
+                    Some(ref name) => names.extend(ident_name(name)),
+                    None => ()
+                }
+            },
+            Some("begin") => defined_names(form, 1, names),
+            _ => ()
         }
     }
 }
-        
+
+/// State of the interpreter that all its contexts share, and that they
+/// don't change by replacing it, so cloning a context is cheap
+#[derive(Debug)]
+pub struct Shared {
+    /// Environment of the builtins, extended by the global environment of
+    /// the program and the one of each library
+    pub base_env: Rc<Env>,
+    pub sources: RefCell<SourceMap>,
+    /// Number of fresh identifiers made so far, e.g. for the renaming done
+    /// when expanding a syntax-rules macro or by gensym
+    pub fresh_ids: Cell<usize>,
+    /// Exception handlers installed by with-exception-handler or guard,
+    /// the current one last
+    pub handlers: RefCell<Vec<Rc<Expr>>>,
+    /// Files being loaded or included, the current one last
+    pub files: RefCell<Vec<PathBuf>>,
+    /// Libraries defined so far, by name
+    pub libraries: RefCell<HashMap<String, Rc<Library>>>,
+    /// Directories where import looks for the files of libraries, after
+    /// the ones of the files being loaded
+    pub library_path: RefCell<Vec<PathBuf>>,
+    /// Port read by read-line, read-char... when they are not given one
+    pub input_port: RefCell<Rc<Expr>>,
+    /// Port written by _print, write-string... when they are not given one
+    pub output_port: RefCell<Rc<Expr>>
+}
 
 #[derive(Clone,Debug)]
 pub struct Context {
     pub expr: Rc<Expr>,
-    pub env: Rc<RefCell<HashMap<String,Rc<Expr>>>>,
-    pub global_env: Rc<RefCell<HashMap<String,Rc<Expr>>>>,
-    pub error: bool
+    pub env: Rc<Env>,
+    pub global_env: Rc<Env>,
+    /// Legacy mode, where () is false too (and not only #f)
+    pub nil_is_false: bool,
+    pub shared: Rc<Shared>
 }
 
 impl Context {
     pub fn new() -> Context {
-        Context {
+        let base_env = Env::new();
+        let global_env = Env::extend(&base_env);
+        let c = Context {
             expr: Rc::new(Expr::Nil),
-            env: Rc::new(RefCell::new((HashMap::new()))),
-            global_env: Rc::new(RefCell::new((HashMap::new()))),
-            error: false
+            env: global_env.clone(),
+            global_env: global_env,
+            nil_is_false: false,
+            shared: Rc::new(Shared {
+                base_env,
+                sources: RefCell::new(SourceMap::new()),
+                fresh_ids: Cell::new(0),
+                handlers: RefCell::new(vec!()),
+                files: RefCell::new(vec!()),
+                libraries: RefCell::new(HashMap::new()),
+                library_path: RefCell::new(vec!()),
+                input_port: RefCell::new(Rc::new(Expr::Port(RefCell::new(Port::stdin())))),
+                output_port: RefCell::new(Rc::new(Expr::Port(RefCell::new(Port::Stdout))))
+            })
+        };
+        builtins::register(&c);
+        c
+    }
+
+    /// Defines a function implemented in Rust in the environment of the
+    /// builtins, so that the program and the libraries can use it.
+    ///
+    /// It is called with the values of its arguments, once their number
+    /// has been checked against arity.
+    pub fn define_primitive<F>(&self, name:&str, arity:Arity, f:F)
+        where F: Fn(&Context, &[Rc<Expr>]) -> Result<Rc<Expr>, EvalError> + 'static {
+        let p = Primitive::new(name, arity, f);
+        self.shared.base_env.define(name.to_string(), Rc::new(Expr::Primitive(p)));
+    }
+
+    // Attach to an error the position of the offending expression or,
+    // if it has none (e.g. it was computed), of the expression being evaluated
+    fn locate(&self, err:EvalError) -> EvalError {
+        if err.span().is_some() {
+            return err;
+        }
+        let span = {
+            let sources = self.shared.sources.borrow();
+            sources.get(&err.expr())
+                .or_else(|| sources.get(&self.expr))
+                .cloned()
+        };
+        match span {
+            Some(span) => EvalError::Located(span, Box::new(err)),
+            None => err
         }
     }
 
-    fn error(&self) -> Context {
-        let mut c = self.set_expr (Expr::Nil);
-        c.error = true;
-        c
+    /// Returns a description of an error, with an excerpt of the source
+    /// code where it happened if it is known
+    pub fn report(&self, err:&EvalError) -> String {
+        let excerpt = match err.span() {
+            Some(span) => span.excerpt(),
+            None => None
+        };
+        match excerpt {
+            Some(excerpt) => format!("{}\n{}", err, excerpt),
+            None => format!("{}", err)
+        }
+    }
+
+    // Error for an ill-formed special form: the offending expression is the
+    // whole form, which is the current expression of the context
+    fn bad_form(&self, name:&str) -> EvalError {
+        EvalError::BadSpecialForm(name.to_string(), self.expr.clone())
     }
 
-    fn error_str(&self, s:&str)-> Context {
-        error!("{}", s);
-        self.error()
+    fn bad_arity(&self, name:&str) -> EvalError {
+        EvalError::Arity(name.to_string(), self.expr.clone())
     }
 
-    pub fn has_error(&self) -> bool {
-        self.error
+    fn bad_type(&self, name:&str, e:Rc<Expr>) -> EvalError {
+        EvalError::TypeMismatch(name.to_string(), e)
     }
-    
+
     pub fn set_expr(&self, expr: Expr) -> Context {
         let mut c = self.clone();
         c.expr = Rc::new(expr);
@@ -116,73 +271,64 @@ impl Context {
         c
     }
 
-    pub fn lookup(&self, ident: &String) -> Context {
-        let env = self.env.borrow();
-        let global_env = self.global_env.borrow();
-        match env.get(ident) {
-            None => {
-                match global_env.get(ident) {
-                    None => {
-                        error!("Lookup: variable {} not found in environment", ident);
-                        self.error()
-                    },
-                    Some(x) => self.set_rcexpr(x.clone())
-                }
-            },
-            Some(x)  => self.set_rcexpr(x.clone())
+    pub fn lookup(&self, ident: &String) -> Result<Context, EvalError> {
+        self.lookup_in(&self.env, ident)
+    }
+
+    // Look for the value of an identifier in the given environment rather
+    // than the one of the context
+    fn lookup_in(&self, env:&Env, ident:&str) -> Result<Context, EvalError> {
+        match env.lookup(ident) {
+            None => Err(EvalError::UnboundVariable(Rc::new(Expr::Ident(ident.to_string())))),
+            Some(x) => Ok(self.set_rcexpr(x))
         }
     }
 
-    // returns a context with a duplicated env hashmap, so inserting into it won't
-    // fuck up parent context
-    pub fn dup_env(&self) -> Context {
+    // Returns a context whose environment is a new, empty frame, extending
+    // the given one
+    fn new_frame(&self, parent:&Rc<Env>) -> Context {
         let mut c = self.clone();
-        let newhash:HashMap<String,Rc<Expr>> = c.env.borrow_mut().clone();
-        c.env = Rc::new(RefCell::new(newhash));
+        c.env = Env::extend(parent);
         c
     }
-    
-    pub fn add_env(&self, ident:String, expr:Rc<Expr>) -> Context {
+
+    /// Binds an identifier in the innermost frame of the environment
+    pub fn add_env(&self, ident:String, expr:Rc<Expr>) -> Result<Context, EvalError> {
         if is_reserved_ident (&ident) {
-            error!("Keyword {} is reserved", ident);
-            self.error()
+            Err(EvalError::ReservedIdent(Rc::new(Expr::Ident(ident))))
         } else {
-            let c = self.dup_env();
-            c.env.borrow_mut().insert(ident, expr);
-            c
+            self.env.define(ident, expr);
+            Ok(self.clone())
         }
     }
 
-    pub fn add_global(&self, ident:String, expr:Rc<Expr>) -> Context {
+    pub fn add_global(&self, ident:String, expr:Rc<Expr>) -> Result<Context, EvalError> {
         if is_reserved_ident (&ident) {
-            error!("Keyword {} is reserved", ident);
-            self.error()
+            Err(EvalError::ReservedIdent(Rc::new(Expr::Ident(ident))))
         } else {
-            let mut global_env = self.global_env.borrow_mut();
-            global_env.insert(ident, expr);
-            self.clone()
+            self.global_env.define(ident, expr);
+            Ok(self.clone())
+        }
+    }
+
+    /// Returns false for #f (and () in legacy mode), true for any other value
+    pub fn is_true(&self, e:&Expr) -> bool {
+        match *e {
+            Expr::Bool(b) => b,
+            Expr::Nil => !self.nil_is_false,
+            _ => true
         }
     }
 
-    fn eval_if_form (&self, p:Rc<Expr>, t:Rc<Expr>, f:Rc<Expr>) -> Context {
+    fn eval_if_form (&self, p:Rc<Expr>, t:Rc<Expr>, f:Rc<Expr>) -> Result<Step, EvalError> {
         let mut c = self.clone();
         c.expr = p.clone();
-        let c = c.eval();
-        match *c.expr {
-            Expr::Nil => {
-                let mut res = c.clone();
-                res.expr = f.clone();
-                res.eval()
-            },
-            _ => { // anything but nil is true
-                let mut res = c.clone();
-                res.expr = t.clone();
-                res.eval()
-            }
-        }
+        let mut c = c.eval()?;
+        c.expr = if self.is_true(&c.expr) {t} else {f};
+        Ok(Step::TailCall(c))
     }
 
-    fn eval_if (&self, e:Rc<Expr>) -> Context {
+    fn eval_if (&self, e:Rc<Expr>) -> Result<Step, EvalError> {
         match *e {
             Expr::Cons(ref p, ref r) =>
                 match **r {
@@ -191,17 +337,17 @@ impl Context {
                             Expr::Cons (ref f, ref r) =>
                                 match **r {
                                     Expr::Nil => self.eval_if_form (p.clone(), t.clone(), f.clone()),
-                                    _ => self.error_str("ill-formed if")
+                                    _ => Err(self.bad_form("if"))
                                 },
-                            _ => self.error_str ("ill-formed if")
+                            _ => Err(self.bad_form("if"))
                         },
-                    _ => self.error_str ("ill-formed if"),
+                    _ => Err(self.bad_form("if")),
                 },
-            _ => self.error_str ("ill-formed if")
+            _ => Err(self.bad_form("if"))
         }
     }
 
-    fn pre_eval_1(&self, e:Rc<Expr>) -> Context {
+    fn pre_eval_1(&self, name:&str, e:Rc<Expr>) -> Result<Context, EvalError> {
         match *e {
             Expr::Cons (ref e1, ref r) =>
                 match **r {
@@ -210,232 +356,16 @@ impl Context {
                         c.expr = e1.clone();
                         c.eval()
                     },
-                    _ => self.error_str ("Too many args")
+                    _ => Err(self.bad_arity(name))
                 },
-            _ => self.error_str ("Arg is not a cons")
+            _ => Err(self.bad_arity(name))
         }
     }
 
-    fn pre_eval_2(&self, e:Rc<Expr>) -> (Rc<Expr>, Rc<Expr>, Context) {
-        match *e {
-            Expr::Cons (ref e1, ref r) =>
-                match **r {
-                    Expr::Cons (ref e2, ref r) => {
-                        match **r {
-                            Expr::Nil => {
-                                let mut c = self.clone();
-                                c.expr = (*e1).clone();
-                                let mut c = c.eval().clone();
-                                let r1 = c.expr.clone();
-                                c = self.clone();
-                                c.expr = (*e2).clone();
-                                let c = c.eval();
-                                let r2 = c.expr.clone();
-                                (r1, r2, c.clone())
-                            },
-                            _ => (Rc::new(Expr::Nil), Rc::new(Expr::Nil), self.error_str("ill-formed operator: too many args"))
-                        }
-                    },
-                    _ => (Rc::new(Expr::Nil), Rc::new(Expr::Nil), self.error_str("ill-formed operator: too many args")),
-                },
-            _ => (Rc::new(Expr::Nil), Rc::new(Expr::Nil), self.error_str("ill-formed operator: too many args"))
-        }        
-    }
-
-    fn eval_equal(&self, e:Rc<Expr>) -> Context {
-        let (r1, r2, mut c) = self.pre_eval_2(e);
-        if c.has_error() {
-            c
-        } else {
-            if r1 == r2 {
-                c.expr = Rc::new(Expr::Ident("t".to_string()));
-            } else {
-                c.expr = Rc::new(Expr::Nil);
-            }
-            c
-        }
-    }
-
-    // Display the value of the expression
-    fn eval_print(&self, e:Rc<Expr>) -> Context {
-        let c = self.pre_eval_1(e);
-        match *c.expr {
-            Expr::String(ref s) => print!("{}", s),
-            _ => print!("{}", *c.expr)
-        }
-        self.set_expr(Expr::Nil)
-    }
-    
-    // Concatenate two values, as a sring
-    fn eval_str(&self, e:Rc<Expr>) -> Context {
-        let (r1,r2,_) = self.pre_eval_2(e);
-        let s1 = match *r1 {
-            Expr::String(ref s) => format!("{}", s),
-            _ => format!("{}", r1)
-        };
-        let s2 = match *r2 {
-            Expr::String(ref s) => format!("{}", s),
-            _ => format!("{}", r2)
-        };
-        let s = format!("{}{}",s1, s2);
-        self.set_expr(Expr::String(s.to_string()))
-    }
-
-    fn eval_plus(&self, e:Rc<Expr>) -> Context {
-        let (r1,r2,c) = self.pre_eval_2(e);
-
-        let expr:Expr = match *r1 {
-            Expr::Integer(x1) => match *r2 {
-                Expr::Integer(x2) => Expr::Integer(x1 + x2),
-                Expr::Float(x2) => Expr::Float((x1 as f64) + x2),
-                _ => return self.error_str("Eval error in +: invalid types for arguments")
-            },
-            Expr::Float(x1) => match *r2 {
-                Expr::Integer(x2) => Expr::Float(x1 + (x2 as f64)),
-                Expr::Float(x2) => Expr::Float(x1 + x2),
-                _ => return self.error_str("Eval error in +: invalid types for arguments")
-            },
-            _ => return self.error_str ("Eval error in +: invalid types for arguments")
-        };
- 
-        let mut new_c = c.clone();
-        new_c.expr = Rc::new(expr);
-        new_c
-    }
-
-    fn eval_sub(&self, e:Rc<Expr>) -> Context {
-        //TODO: implement for only one argument
-        let (r1,r2,c) = self.pre_eval_2(e);
-
-        let expr:Expr = match *r1 {
-            Expr::Integer(x1) => match *r2 {
-                Expr::Integer(x2) => Expr::Integer(x1 - x2),
-                Expr::Float(x2) => Expr::Float((x1 as f64) - x2),
-                _ => return self.error_str("Eval error in -: invalid types for arguments")
-            },
-            Expr::Float(x1) => match *r2 {
-                Expr::Integer(x2) => Expr::Float(x1 - (x2 as f64)),
-                Expr::Float(x2) => Expr::Float(x1 - x2),
-                _ => return self.error_str("Eval error in -: invalid types for arguments")
-            },
-            _ => return self.error_str("Eval error in -: invalid types for arguments")
-        };
-
-        let mut new_c = c.clone();
-        new_c.expr = Rc::new(expr);
-        new_c
-    }
-
-    fn eval_gt(&self, e:Rc<Expr>) -> Context {
-        let (r1,r2,c) = self.pre_eval_2(e);
-
-        let is_lt = match *r1 {
-            Expr::Integer(x1) => match *r2 {
-                Expr::Integer(x2) => x1 > x2,
-                Expr::Float(x2) => (x1 as f64) > x2,
-                _ => return self.error_str("Eval error in >: invalid types for arguments")
-            },
-            Expr::Float(x1) => match *r2 {
-                Expr::Integer(x2) => x1 > (x2 as f64),
-                Expr::Float(x2) => x1 > x2,
-                _ => return self.error_str("Eval error in >: invalid types for arguments")
-            },
-            Expr::String(ref s1) => match *r2 {
-                Expr::String(ref s2) => s1 > s2,
-                _ => return self.error_str("Eval error in >: invalid types for arguments")
-            },
-            _ => return self.error_str("Eval error in >: invalid types for arguments")
-        };
-
-        let expr = if is_lt {
-            Expr::Ident("t".to_string())
-        } else {
-            Expr::Nil
-        };
-        
-        c.set_expr(expr)
-    }
-
-    fn eval_lt(&self, e:Rc<Expr>) -> Context {
-        let (r1,r2,c) = self.pre_eval_2(e);
-
-        let is_lt = match *r1 {
-            Expr::Integer(x1) => match *r2 {
-                Expr::Integer(x2) => x1 < x2,
-                Expr::Float(x2) => (x1 as f64) < x2,
-                _ => return self.error_str("Eval error in <: invalid types for arguments")
-            },
-            Expr::Float(x1) => match *r2 {
-                Expr::Integer(x2) => x1 < (x2 as f64),
-                Expr::Float(x2) => x1 < x2,
-                _ => return self.error_str("Eval error in <: invalid types for arguments")
-            },
-            Expr::String(ref s1) => match *r2 {
-                Expr::String(ref s2) => s1 < s2,
-                _ => return self.error_str("Eval error in <: invalid types for arguments")
-            },
-            _ => return self.error_str("Eval error in <: invalid types for arguments")
-        };
-
-        let expr = if is_lt {
-            Expr::Ident("t".to_string())
-        } else {
-            Expr::Nil
-        };
-        
-        c.set_expr(expr)
-    }
-
-    fn eval_mul(&self, e:Rc<Expr>) -> Context {
-        let (r1,r2,c) = self.pre_eval_2(e);
-
-        let expr:Expr = match *r1 {
-            Expr::Integer(x1) => match *r2 {
-                Expr::Integer(x2) => {
-                    Expr::Integer(x1 * x2)
-                },
-                Expr::Float(x2) => Expr::Float((x1 as f64) * x2),
-                _ => return self.error_str("Eval error in *: invalid types for arguments")
-            },
-            Expr::Float(x1) => match *r2 {
-                Expr::Integer(x2) => Expr::Float(x1 * (x2 as f64)),
-                Expr::Float(x2) => Expr::Float(x1 * x2),
-                _ => return self.error_str("Eval error in *: invalid types for arguments")
-            },
-            _ => return self.error_str("Eval error in *: invalid types for arguments")
-        };
-
-        let mut new_c = c.clone();
-        new_c.expr = Rc::new(expr);
-        new_c
-    }
-    
-    fn eval_div(&self, e:Rc<Expr>) -> Context {
-        let (r1,r2,c) = self.pre_eval_2(e);
-
-        let expr:Expr = match *r1 {
-            Expr::Integer(x1) => match *r2 {
-                Expr::Integer(x2) => Expr::Integer(x1 / x2),
-                Expr::Float(x2) => Expr::Float((x1 as f64) / x2),
-                _ => return self.error_str("Eval error in /: invalid types for arguments")
-            },
-            Expr::Float(x1) => match *r2 {
-                Expr::Integer(x2) => Expr::Float(x1 / (x2 as f64)),
-                Expr::Float(x2) => Expr::Float(x1 / x2),
-                _ => return self.error_str("Eval error in /: invalid types for arguments")
-            },
-            _ => return self.error_str("Eval error in /: invalid types for arguments")
-        };
-
-        let mut new_c = c.clone();
-        new_c.expr = Rc::new(expr);
-        new_c
-    }
-
-    fn eval_def(&self, e:Rc<Expr>) -> Context {
+    fn eval_def(&self, e:Rc<Expr>) -> Result<Context, EvalError> {
         let r1:Rc<Expr>;
         let r2:Rc<Expr>;
-        let mut new_c:Context;
+        let new_c:Context;
 
         match *e {
             Expr::Cons(ref e1, ref r) =>
@@ -446,71 +376,88 @@ impl Context {
                                 r1 = e1.clone();
                                 let mut c = self.clone();
                                 c.expr = e2.clone();
-                                new_c = c.eval();
+                                new_c = c.eval()?;
                                 r2 = new_c.expr.clone()
                             }
-                            _ => return self.error_str("Too many arguments to def")
+                            _ => return Err(self.bad_form("def"))
                         },
-                    _ => return self.error_str("Wrong number of arguments to def")
+                    _ => return Err(self.bad_form("def"))
                 },
-            _ => return self.error_str("Wrong number of argumets to def")
+            _ => return Err(self.bad_form("def"))
         }
-        
+
         match *r1 {
             Expr::Ident(ref s) => {
-                let mut c = new_c.add_global(s.clone(), r2.clone());
+                let mut c = new_c.add_global(s.clone(), r2.clone())?;
                 c.expr = r2.clone();
-                c
+                Ok(c)
             }
-            _ => self.error_str("def must take an ident as first parameter")
+            _ => Err(self.bad_form("def"))
         }
     }
 
-    fn eval_eval(&self, e:Rc<Expr>) -> Context {
-        let c = self.pre_eval_1(e);
-        if c.has_error() {
-            c
+    fn eval_set(&self, e:Rc<Expr>) -> Result<Context, EvalError> {
+        let (ident, value) = match *e {
+            Expr::Cons(ref e1, ref r) =>
+                match **r {
+                    Expr::Cons(ref e2, ref r) =>
+                        match **r {
+                            Expr::Nil => {
+                                let mut c = self.clone();
+                                c.expr = e2.clone();
+                                (e1.clone(), c.eval()?.expr)
+                            }
+                            _ => return Err(self.bad_form("set!"))
+                        },
+                    _ => return Err(self.bad_form("set!"))
+                },
+            _ => return Err(self.bad_form("set!"))
+        };
+
+        let found = match *ident {
+            Expr::Ident(ref s) => self.env.set(s, value.clone()),
+            Expr::Alias(ref s, ref env) => env.set(s, value.clone()),
+            _ => return Err(self.bad_form("set!"))
+        };
+        if found {
+            Ok(self.set_rcexpr(value))
         } else {
-            c.eval()
+            Err(EvalError::UnboundVariable(ident.clone()))
         }
     }
 
-    
-    fn eval_car (&self, e:Rc<Expr>) -> Context {
-        let mut c = self.pre_eval_1(e);
-        let e = c.expr.clone();
-        match *e {
-            Expr::Cons (ref car, _) => {
-                c.expr = car.clone();
-                c
-            }
-            _ => self.error_str ("Error: car must take a list")
-        }
+    fn eval_eval(&self, e:Rc<Expr>) -> Result<Step, EvalError> {
+        let c = self.pre_eval_1("eval", e)?;
+        Ok(Step::TailCall(c))
     }
 
-    fn eval_cdr (&self, e:Rc<Expr>) -> Context {
-        let mut c = self.pre_eval_1(e);
-        let e = c.expr.clone();
-        match *e {
-            Expr::Cons (_, ref cdr) => {
-                c.expr = cdr.clone();
-                c
-            }
-            _ => self.error_str ("Error: cdr must take a list")
+    // Evaluate expressions in sequence, the last one being in tail position
+    fn eval_do(&self, e:Rc<Expr>) -> Result<Step, EvalError> {
+        let mut e = e;
+        loop {
+            let next = match *e {
+                Expr::Nil => return Ok(Step::Value(self.set_expr(Expr::Nil))),
+                Expr::Cons(ref e1, ref r) => {
+                    let mut c = self.clone();
+                    c.expr = e1.clone();
+                    if let Expr::Nil = **r {
+                        return Ok(Step::TailCall(c));
+                    }
+                    c.eval()?;
+                    r.clone()
+                },
+                _ => return Err(self.bad_form("do"))
+            };
+            e = next;
         }
     }
 
-    fn eval_cons (&self, e:Rc<Expr>) -> Context {
-        let (r1,r2, mut c) = self.pre_eval_2(e);
-        c.expr = Rc::new(Expr::Cons(r1.clone(),r2.clone()));
-        c
-    }
 
-    fn eval_defmacro (&self, e:Rc<Expr>) -> Context {
+    fn eval_defmacro (&self, e:Rc<Expr>) -> Result<Context, EvalError> {
         let name:Rc<Expr>;
         let body:Rc<Expr>;
         let args:Rc<Expr>;
-        
+
         match *e {
             Expr::Cons (ref n, ref r) => match **r {
                 Expr::Cons (ref a, ref r) => match **r {
@@ -520,455 +467,1233 @@ impl Context {
                             body = b.clone();
                             args = a.clone();
                         },
-                        _ => return self.error_str("Too many arguments to defmacro")
+                        _ => return Err(self.bad_form("defmacro"))
                     },
-                    _ => return self.error_str("Wrong arguments for defmacro")
+                    _ => return Err(self.bad_form("defmacro"))
                 },
-                _ => return self.error_str("Wrong arguments for defmacro")
+                _ => return Err(self.bad_form("defmacro"))
             },
-            _ => return self.error_str("Wrong arguments for defmacro")
+            _ => return Err(self.bad_form("defmacro"))
         }
 
         let n:String = match *name {
             Expr::Ident(ref s) => s.clone(),
-            _ => return self.error_str("Error: macro name is not an ident")
+            _ => return Err(self.bad_form("defmacro"))
         };
 
         // todo check that args are all idents
-        let c = self.set_expr(Expr::Macro(args.clone(), body.clone()));
+        let c = self.set_expr(Expr::Macro(args.clone(), body.clone(), self.global_env.clone()));
         c.add_global(n, c.expr.clone())
     }
 
-    // Collects all idents used by a lambda,
-    fn collect_idents(&self,
-                      expr:&Expr,
-                      ids: &mut HashSet<String>,
-                      ignore: &HashSet<String>,
-                      quote: bool) {
-        match *expr {
-            Expr::Ident(ref s) => {
-                if !quote && !ignore.contains(s) &&
-                    !is_reserved_ident(s) && !self.global_env.borrow().contains_key(s) {
-                        ids.insert(s.clone());
-                }
-            },
-            Expr::Cons(ref e1, ref e2) => {
-                match **e1 {
-                    Expr::Ident(ref s) => {
-                        if "lambda".to_string() == s.clone() {
-                            if !quote {
-                                // not our problem (for now)
-                            } else {
-                                self.collect_idents(e2, ids, ignore, quote);
-                            }
-                        } else {
-                            // if it's a macro we must not collect idents either(because it can declare variables and all)
-                            let is_macro = if quote {
-                                false
-                            } else {
-                                let env = self.global_env.borrow();
-                                let r = env.get(s);
-                                match r {
-                                    None => false,
-                                    Some(ref e) => match ***e {
-                                        Expr::Macro(_,_) => true,
-                                        _ => false
-                                    }
-                                }
-                            };
-                            if !is_macro {
-                                self.collect_idents(e1, ids, ignore, quote);
-                                self.collect_idents(e2, ids, ignore, quote);
-                            }
-                        }
-                    },
-                    _ => {
-                        self.collect_idents(e1, ids, ignore, quote);
-                        self.collect_idents(e2, ids, ignore, quote);
-                    }
-                }
-            },
-            Expr::Quasiquote(ref e) => {
-                self.collect_idents(e, ids, ignore, true);
-            },
-            Expr::Unquote(ref e) => {
-                self.collect_idents(e, ids, ignore, false);
+    // (syntax-rules (literals...) (pattern template)...)
+    fn eval_syntax_rules (&self, e:Rc<Expr>) -> Result<Context, EvalError> {
+        match SyntaxRules::new(&e) {
+            Some(s) => Ok(self.set_expr(Expr::SyntaxRules(s, self.env.clone()))),
+            None => Err(self.bad_form("syntax-rules"))
+        }
+    }
+
+    // Evaluate the specification of a macro, which must give a
+    // syntax-rules macro
+    fn eval_syntax_spec (&self, form:&str, spec:Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        let mut c = self.clone();
+        c.expr = spec;
+        let v = c.eval()?.expr;
+        match *v {
+            Expr::SyntaxRules(_, _) => Ok(v),
+            _ => Err(self.bad_type(form, v.clone()))
+        }
+    }
+
+    // (define-syntax name (syntax-rules ...)): like define, binds the
+    // macro in the innermost frame
+    fn eval_define_syntax (&self, e:Rc<Expr>) -> Result<Context, EvalError> {
+        let (name, spec) = match *e {
+            Expr::Cons(ref name, ref r) => match (&**name, &**r) {
+                (Expr::Ident(s), Expr::Cons(spec, r)) if **r == Expr::Nil => (s.clone(), spec.clone()),
+                _ => return Err(self.bad_form("define-syntax"))
             },
-            _ => ()
+            _ => return Err(self.bad_form("define-syntax"))
+        };
+        let v = self.eval_syntax_spec("define-syntax", spec)?;
+        let c = self.add_env(name, v.clone())?;
+        Ok(c.set_rcexpr(v))
+    }
+
+    // (let-syntax ((name (syntax-rules ...))...) body...)
+    fn eval_let_syntax (&self, form:&str, e:Rc<Expr>) -> Result<Step, EvalError> {
+        let (bindings, body) = self.let_parts(form, &e)?;
+        let mut c = self.new_frame(&self.env);
+        for (name, spec) in bindings {
+            let v = c.eval_syntax_spec(form, spec)?;
+            c = c.add_env(name, v)?;
         }
+        c.eval_do(body)
     }
 
-        
-    
-    // This method has two purposes:
-    // 1) Checks that all args are valid (i.e) are idents
-    // 2) Add idents in a vector so we don't need to add them to closure env
-    fn lambda_verify_args (&self,
-                           e:&Expr,
-                           v:&mut HashSet<String>) -> bool {
+    // Checks that all args are valid (i.e) are idents
+    fn lambda_verify_args (&self, e:&Expr) -> bool {
         match *e {
-            Expr::Cons(ref e1, ref e2) => self.lambda_verify_args (e1, v) &&
-                self.lambda_verify_args (e2, v),
-            Expr::Ident(ref s) => {
-                v.insert(s.clone());
-                true
-            },
+            Expr::Cons(ref e1, ref e2) => self.lambda_verify_args (e1) &&
+                self.lambda_verify_args (e2),
+            Expr::Ident(_) => true,
             Expr::Nil => true,
-            _ => {
-                error!("Error in lambda declaration: invalid form for args (must be a list of idents");
-                false
-            }
+            _ => false
         }
     }
 
-    fn eval_lambda (&self, e:Rc<Expr>) -> Context {
-        let body:Rc<Expr>;
-        let args:Rc<Expr>;
-        let mut name:Option<String> = None;
-
-        match *e {
-            Expr::Cons(ref a, ref r) =>
-                match **r {
-                    Expr::Cons (ref b, ref r) =>
-                        match **r {
-                            Expr::Nil => {
-                                args = a.clone();
-                                body = b.clone();
-                            },
-                            Expr::Cons(ref c, ref r) =>
-                                match **r {
-                                    Expr::Nil => {
-                                        match **a {
-                                            Expr::Ident(ref s) =>  {
-                                                name = Some(s.clone());
-                                                args = b.clone();
-                                                body = c.clone();
-                                            }
-                                            _ => {
-                                                error!("Error in, lambda for name, expected ident, got {:?}", a);
-                                                return self.error();
-                                            }
-                                        }
-                                            
-                                    },
-                                    _ => return self.error_str("Too many arguments to lambda")
-                                },
-                            _ => return self.error_str("Wrong arguments to lambda")
+    // Split a let form into its bindings, a list of (name expr), and its
+    // body, which can't be empty
+    fn let_parts (&self, form:&str, e:&Rc<Expr>) -> Result<(Vec<Binding>, Rc<Expr>), EvalError> {
+        let (bindings, body) = match **e {
+            Expr::Cons(ref bindings, ref body) if **body != Expr::Nil =>
+                (bindings.clone(), body.clone()),
+            _ => return Err(self.bad_form(form))
+        };
+        let mut res = vec!();
+        let mut l = bindings;
+        loop {
+            let next = match *l {
+                Expr::Nil => return Ok((res, body)),
+                Expr::Cons(ref b, ref r) => {
+                    match **b {
+                        Expr::Cons(ref name, ref r2) => match (&**name, &**r2) {
+                            (Expr::Ident(s), Expr::Cons(init, r3)) if **r3 == Expr::Nil =>
+                                res.push((s.clone(), init.clone())),
+                            _ => return Err(self.bad_form(form))
                         },
-                    _ => return self.error_str ("Wrong arguments to lambda")
+                        _ => return Err(self.bad_form(form))
+                    }
+                    r.clone()
                 },
-            _ => return self.error_str ("Wrong arguments to lambda")
+                _ => return Err(self.bad_form(form))
+            };
+            l = next;
         }
+    }
 
-        // Collect all idents in body, except those in args,
-        // and adds them to an its environment
-        let mut ignore:HashSet<String> = HashSet::new();
-        if self.lambda_verify_args (&*args, &mut ignore) {
-            match name {
-                Some(ref s) => {
-                    ignore.insert(s.clone());
-                }
-                None => ()
+    // (let ((name expr)...) body...): the exprs are evaluated in the current
+    // environment, and the body in a new frame binding their values
+    fn eval_let (&self, e:Rc<Expr>) -> Result<Step, EvalError> {
+        if let Expr::Cons(ref name, ref r) = *e {
+            if let Expr::Ident(ref s) = **name {
+                return self.eval_named_let(s, r);
             }
-            let mut ids:HashSet<String> = HashSet::new();
-            self.collect_idents(&*body, &mut ids, &ignore, false);
-            for i in ignore {
-                ids.remove(&i);
+        }
+        let (bindings, body) = self.let_parts("let", &e)?;
+        let mut values = vec!();
+        for (name, init) in bindings {
+            let mut c = self.clone();
+            c.expr = init;
+            values.push((name, c.eval()?.expr));
+        }
+        let mut c = self.new_frame(&self.env);
+        for (name, v) in values {
+            c = c.add_env(name, v)?;
+        }
+        c.eval_do(body)
+    }
+
+    // (let name ((arg expr)...) body...) calls a function named name, visible
+    // in its body, so loops can be written as tail calls to it
+    fn eval_named_let (&self, name:&str, e:&Rc<Expr>) -> Result<Step, EvalError> {
+        let (bindings, body) = self.let_parts("let", e)?;
+        let mut args_name = Rc::new(Expr::Nil);
+        let mut values = Rc::new(Expr::Nil);
+        for (arg, _) in bindings.iter().rev() {
+            args_name = Rc::new(Expr::Cons(Rc::new(Expr::Ident(arg.clone())), args_name));
+        }
+        // the inits are evaluated in order
+        let mut inits = vec!();
+        for (_, init) in bindings {
+            let mut c = self.clone();
+            c.expr = init;
+            inits.push(c.eval()?.expr);
+        }
+        for v in inits.into_iter().rev() {
+            values = Rc::new(Expr::Cons(v, values));
+        }
+        let f = self.make_lambda("let", name.to_string(), args_name, body)?;
+        self.eval_fncall(f, values, true)
+    }
+
+    // (let* ((name expr)...) body...): each expr is evaluated in a frame
+    // binding the names that precede it
+    fn eval_let_star (&self, e:Rc<Expr>) -> Result<Step, EvalError> {
+        let (bindings, body) = self.let_parts("let*", &e)?;
+        let mut c = self.new_frame(&self.env);
+        for (name, init) in bindings {
+            let mut init_c = c.clone();
+            init_c.expr = init;
+            let v = init_c.eval()?.expr;
+            c = c.new_frame(&c.env).add_env(name, v)?;
+        }
+        c.eval_do(body)
+    }
+
+    // (letrec ((name expr)...) body...): the exprs are evaluated in order,
+    // in the frame where the names are bound, so functions they define can
+    // refer to each other
+    fn eval_letrec (&self, form:&str, e:Rc<Expr>) -> Result<Step, EvalError> {
+        let (bindings, body) = self.let_parts(form, &e)?;
+        let mut c = self.new_frame(&self.env);
+        for (name, init) in bindings {
+            let mut init_c = c.clone();
+            init_c.expr = init;
+            let v = init_c.eval()?.expr;
+            c = c.add_env(name, v)?;
+        }
+        c.eval_do(body)
+    }
+
+    // Build a closure over the current environment; body is the list of
+    // forms of the body, which can't be empty
+    fn make_lambda (&self, form:&str, name:String, args:Rc<Expr>, body:Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        if let Expr::Cons(_, _) = *body {
+            if self.lambda_verify_args (&args) {
+                return Ok(Rc::new(Expr::Lambda(name, args, body, self.env.clone())));
             }
-            let env = if ids.is_empty() {
-                None
-            } else {
-                let mut e:HashMap<String,Rc<Expr>> = HashMap::new();
-                for k in ids {
-                    let c = self.lookup(&k);
-                    let v = c.expr.clone();
-                    if c.has_error() {
-                        error!("Lambda depends on ident {} but it can't be found in this context", &k);
-                        return self.error();
-                     } else {
-                        e.insert(k.clone(),v);
-                    }
-                }
-                Some(e)
-            };
-            let l_name:String = match name {
-                None => "".to_string(),
-                Some(s) => s.clone()
-            };
-            self.set_expr (Expr::Lambda(l_name, args,body, env))
-        } else {
-            self.error()
         }
+        Err(self.bad_form(form))
+    }
+
+    // (lambda [name] (args...) body...)
+    fn eval_lambda (&self, e:Rc<Expr>) -> Result<Context, EvalError> {
+        let (name, rest) = match *e {
+            Expr::Cons(ref a, ref r) => match **a {
+                Expr::Ident(ref s) => (s.clone(), r.clone()),
+                _ => ("".to_string(), e.clone())
+            },
+            _ => return Err(self.bad_form("lambda"))
+        };
+        match *rest {
+            Expr::Cons(ref args, ref body) => {
+                let f = self.make_lambda("lambda", name, args.clone(), body.clone())?;
+                Ok(self.set_rcexpr(f))
+            },
+            _ => Err(self.bad_form("lambda"))
+        }
+    }
+
+    // (define name expr) or (define (name args...) body...).
+    // The binding is added to the innermost frame: the global environment at
+    // top level, or the frame of the function call in a body, where it is
+    // visible by the following forms and the closures they define.
+    fn eval_define (&self, e:Rc<Expr>) -> Result<Context, EvalError> {
+        let (name, value) = match *e {
+            Expr::Cons(ref target, ref r) => match (&**target, &**r) {
+                (Expr::Ident(s), Expr::Cons(expr, r)) if **r == Expr::Nil => {
+                    let mut c = self.clone();
+                    c.expr = expr.clone();
+                    (s.clone(), c.eval()?.expr)
+                },
+                (Expr::Cons(name, args), _) => match **name {
+                    Expr::Ident(ref s) =>
+                        (s.clone(), self.make_lambda("define", s.clone(), args.clone(), r.clone())?),
+                    _ => return Err(self.bad_form("define"))
+                },
+                _ => return Err(self.bad_form("define"))
+            },
+            _ => return Err(self.bad_form("define"))
+        };
+        let c = self.add_env(name, value.clone())?;
+        Ok(c.set_rcexpr(value))
     }
 
 
     // Eval all elements in a list, returning the list of all eval'ed elements
-    fn eval_all_in_list(&self, expr:Rc<Expr>) -> Context
+    fn eval_all_in_list(&self, expr:Rc<Expr>) -> Result<Context, EvalError>
     {
-        match *expr {
-            Expr::Nil => self.set_expr(Expr::Nil),
-            Expr::Cons(ref e, ref r) => {
-                let mut c = self.clone();
-                c.expr = e.clone();
-                c = c.eval();
-                let evaled_car = c.expr;
-                c = self.eval_all_in_list(r.clone());
-                let evaled_cdr = c.expr;
-                self.set_expr(Expr::Cons(evaled_car, evaled_cdr))
-            },
-            _ => self.error_str("Wrong list for eval_all_in_list")
+        let evaled = self.eval_args(expr)?;
+        let mut res = Rc::new(Expr::Nil);
+        for e in evaled.into_iter().rev() {
+            res = Rc::new(Expr::Cons(e, res));
         }
+        Ok(self.set_rcexpr(res))
     }
-    
 
-    /// Check args of a function or macro call, make them correspond and add them to environment    
+    // Eval all elements in a list, returning a vector of the values
+    fn eval_args(&self, expr:Rc<Expr>) -> Result<Vec<Rc<Expr>>, EvalError>
+    {
+        let mut evaled:Vec<Rc<Expr>> = vec!();
+        let mut expr = expr;
+        loop {
+            let next = match *expr {
+                Expr::Nil => break,
+                Expr::Cons(ref e, ref r) => {
+                    let mut c = self.clone();
+                    c.expr = e.clone();
+                    c = c.eval()?;
+                    evaled.push(c.expr);
+                    r.clone()
+                },
+                _ => return Err(self.bad_type("function call", expr.clone()))
+            };
+            expr = next;
+        }
+        Ok(evaled)
+    }
+
+
+    /// Check args of a function or macro call, make them correspond and add them to environment.
+    /// If quoted is true, the args are bound as they are instead of being evaluated
+    /// (for macros, or for functions called by apply).
     fn eval_fn_args (&self,
+                     name:&str,
                      args_name:Rc<Expr>,
                      args:Rc<Expr>,
-                     is_macro:bool,
-                     old_c:&Context) -> Context {
-        match *args_name {
-            Expr::Nil => match *args {
-                Expr::Nil => self.clone(), // no args in both cases
-                _ => self.error_str("1Error in function call: number of arguments don't match")
-            },
-            Expr::Cons(ref a1, ref r1) => {
-                info!("{:?}", a1);
-                if let Expr::Ident(ref s) = **a1 {
-                    if s == "&" { // case for catchall argument name
-                        if let Expr::Cons(ref catchall, ref r1_bis) = **r1 {
-                                    match **r1_bis {
-                                        Expr::Nil => {
-                                            match **catchall {
-                                                Expr::Ident(ref s) => { 
-                                                    let v = if !is_macro {
-                                                        let c = self.eval_all_in_list(args.clone());
-                                                        c.expr
-                                                    } else {
-                                                        args.clone()
-                                                    };
-                                                    info!("args: {:?}", args);
-                                                    info!("evalued {} to {:?}", s, v);
-                                                    return self.add_env(s.clone(), v);
-                                                },
-                                                _ => return self.error_str("Catch all is not an ident")
-                                            }},
-                                        _ => {
-                                            return self.error_str("Catch all argument must be the last of the list")
-                                        }}}}}
-                match *args{
-                    Expr::Cons(ref a2, ref r2) => {
-                        match **a1 {
-                            Expr::Ident(ref s) => {
-                                let mut c = old_c.clone();
-                                c.expr = a2.clone();
-                                c = if is_macro {c} else {c.eval()}; //WRONG ENV TO EVAL THIS ?
-                                let v = c.expr.clone();
-                                info!("evalued {} to {:?}", s, v);
-                                c = self.add_env(s.clone(), v);
-                                c.eval_fn_args(r1.clone(),r2.clone(), is_macro, old_c)
+                     quoted:bool,
+                     old_c:&Context) -> Result<Context, EvalError> {
+        let mut c = self.clone();
+        let mut args_name = args_name;
+        let mut args = args;
+        loop {
+            let (a1, r1) = match *args_name {
+                Expr::Nil => return match *args {
+                    Expr::Nil => Ok(c), // no args in both cases
+                    _ => Err(old_c.bad_arity(name))
+                },
+                Expr::Cons(ref a1, ref r1) => (a1.clone(), r1.clone()),
+                // (x y . rest)
+                Expr::Ident(_) => return c.bind_rest(name, &args_name, args, quoted, old_c),
+                _ => return Err(old_c.bad_type(name, args_name.clone()))
+            };
+            info!("{:?}", a1);
+            if let Expr::Ident(ref s) = *a1 {
+                if s == "&" { // case for catchall argument name
+                    if let Expr::Cons(ref catchall, ref r1_bis) = *r1 {
+                        match **r1_bis {
+                            Expr::Nil => {
+                                return c.bind_rest(name, catchall, args, quoted, old_c)
                             },
-                            _ => self.error_str("Argument name is not an ident!")                            
-                        }},
-                    _ => self.error_str("2Error in function call: number of arguments don't match")
+                            _ => {
+                                return Err(old_c.bad_type(name, args_name.clone()))
+                            }}}}}
+            let r2 = match *args {
+                Expr::Cons(ref a2, ref r2) => {
+                    match *a1 {
+                        Expr::Ident(ref s) => {
+                            let v = if quoted {
+                                a2.clone()
+                            } else {
+                                let mut arg_c = old_c.clone();
+                                arg_c.expr = a2.clone();
+                                arg_c.eval()?.expr
+                            };
+                            info!("evalued {} to {:?}", s, v);
+                            c = c.add_env(s.clone(), v)?;
+                            r2.clone()
+                        },
+                        _ => return Err(old_c.bad_type(name, a1.clone()))
                     }},
-            _ => self.error_str("Fn arg names must be a list!")
+                _ => return Err(old_c.bad_arity(name))
+            };
+            args_name = r1;
+            args = r2;
         }
     }
 
-    fn eval_macro (&self,
-                   args_name:Rc<Expr>,
-                   body:Rc<Expr>,
-                   args:Rc<Expr>) -> Context {
-        let mut c = self.eval_fn_args(args_name, args, true, self);
-        if c.has_error() {
-            self.error()
-        } else {
-            c.expr = body;
-            let mut res = c.eval();
-            res.env = self.env.clone();
-            // for debug
-            info!("Debug: macroexpand gives\n{}", res.expr.clone());
-            res.eval()
+    // Bind the list of the remaining arguments to the rest parameter
+    fn bind_rest (&self,
+                  name:&str,
+                  rest:&Rc<Expr>,
+                  args:Rc<Expr>,
+                  quoted:bool,
+                  old_c:&Context) -> Result<Context, EvalError> {
+        match **rest {
+            Expr::Ident(ref s) => {
+                let v = if quoted {
+                    args
+                } else {
+                    old_c.eval_all_in_list(args)?.expr
+                };
+                info!("evalued {} to {:?}", s, v);
+                self.add_env(s.clone(), v)
+            },
+            _ => Err(old_c.bad_type(name, rest.clone()))
         }
     }
 
-    fn eval_fncall (&self,
-                    name:String,
-                    args_name:Rc<Expr>,
-                    body:Rc<Expr>,
-                    args:Rc<Expr>,
-                    env:&Option<HashMap<String,Rc<Expr>>>) -> Context {
-        let mut c = self.clone();
+    // Call the lambda f with the arguments args (evaluated, unless quoted
+    // is true)
+    fn eval_fncall (&self, f:Rc<Expr>, args:Rc<Expr>, quoted:bool) -> Result<Step, EvalError> {
+        let (name, args_name, body, env) = match *f {
+            Expr::Lambda(ref name, ref args_name, ref body, ref env) => (name, args_name, body, env),
+            _ => return Err(EvalError::NotAFunction(f.clone()))
+        };
+        // The body is evaluated in a new frame extending the environment
+        // the lambda was defined in
+        let mut c = self.new_frame(env);
         if !name.is_empty() {
-            c = c.add_env(name.clone(), Rc::new(Expr::Lambda(name.clone(),args_name.clone(),body.clone(),None)));
-        } else {
-            c = c.dup_env();
+            c = c.add_env(name.clone(), f.clone())?;
         }
-        merge_envs(&mut c.env.borrow_mut(), env);
- 
-        let mut c = c.eval_fn_args (args_name, args, false, self);
-        if c.has_error() {
-            self.error()
-        } else {
-            c.expr = body;
-            let mut res = c.eval();
-            res.env = self.env.clone();
-            res
+
+        let fn_name = if name.is_empty() {"lambda"} else {name.as_ref()};
+        let c = c.eval_fn_args (fn_name, args_name.clone(), args, quoted, self)?;
+        c.eval_do(body.clone())
+    }
+
+    // Quasiquote an element of a list or a vector, and push the result to
+    // elems; the elements of the value of ,@ at depth 0 are pushed instead
+    fn quasiquote_elem (&self, e:&Rc<Expr>, depth:usize, elems:&mut Vec<Rc<Expr>>) -> Result<(), EvalError> {
+        match **e {
+            Expr::UnquoteSplicing(ref spliced) if depth == 0 => {
+                let mut c = self.clone();
+                c.expr = spliced.clone();
+                let v = c.eval()?.expr;
+                let mut l = v.clone();
+                while let Expr::Cons(ref x, ref r) = *l.clone() {
+                    elems.push(x.clone());
+                    l = r.clone();
+                }
+                if *l != Expr::Nil {
+                    return Err(self.bad_type("unquote-splicing", v));
+                }
+            },
+            _ => elems.push(self.set_rcexpr(e.clone()).eval_quasiquote(depth)?.expr)
         }
+        Ok(())
     }
 
-    fn eval_quasiquote (&self) -> Context {
+    // Quasiquote the current expression. depth is the number of
+    // quasiquotes it is nested in, besides the one being evaluated: only
+    // unquotes at depth 0 are evaluated, the others are kept, with their
+    // content quasiquoted at a lower depth.
+    fn eval_quasiquote (&self, depth:usize) -> Result<Context, EvalError> {
+        let quasiquote = |e:&Rc<Expr>, depth| -> Result<Rc<Expr>, EvalError> {
+            Ok(self.set_rcexpr(e.clone()).eval_quasiquote(depth)?.expr)
+        };
         match *self.expr {
-            Expr::Unquote(ref e) => {
+            Expr::Unquote(ref e) if depth == 0 => {
                 let mut c = self.clone();
                 c.expr = e.clone();
                 c.eval()
             },
-            Expr::Cons(ref car, ref cdr) => {
-                let mut c = self.clone();
-                c.expr = car.clone();
-                c = c.eval_quasiquote();
-                let car = c.expr.clone();
-                c.expr = cdr.clone();
-                c = c.eval_quasiquote();
-                let cdr = c.expr.clone();
-                c.set_expr(Expr::Cons(car,cdr))
+            Expr::Unquote(ref e) => Ok(self.set_expr(Expr::Unquote(quasiquote(e, depth - 1)?))),
+            Expr::UnquoteSplicing(_) if depth == 0 => Err(self.bad_form("unquote-splicing")),
+            Expr::UnquoteSplicing(ref e) =>
+                Ok(self.set_expr(Expr::UnquoteSplicing(quasiquote(e, depth - 1)?))),
+            Expr::Quasiquote(ref e) => Ok(self.set_expr(Expr::Quasiquote(quasiquote(e, depth + 1)?))),
+            Expr::Cons(_, _) => {
+                // the elements are processed in a loop, so that long lists
+                // don't overflow the stack
+                let mut elems = vec!();
+                let mut l = self.expr.clone();
+                while let Expr::Cons(ref car, ref cdr) = *l.clone() {
+                    self.quasiquote_elem(car, depth, &mut elems)?;
+                    l = cdr.clone();
+                }
+                let mut res = quasiquote(&l, depth)?;
+                for e in elems.into_iter().rev() {
+                    res = Rc::new(Expr::Cons(e, res));
+                }
+                Ok(self.set_rcexpr(res))
             },
-            _ => self.clone()
-        }
-    }
-
-    // Checks the types of an expression
-    fn eval_type_check(&self, t:&str, e:Rc<Expr>) -> Context {
-        let c = self.pre_eval_1(e);
-        let e = c.expr.clone();
-        let is_ok =  match t {
-            "_nil?" => if let Expr::Nil = *e {true} else {false},
-            "_list?" => if let Expr::Cons(_,_) = *e {true} else {false},
-            "_lambda?" => if let Expr::Lambda(_,_,_,_) = *e {true} else {false},
-            "_integer?" => if let Expr::Integer(_) = *e {true} else {false},
-            "_float?" => if let Expr::Float(_) = *e {true} else {false},
-            "_ident?" => if let Expr::Ident(_) = *e {true} else {false},
-            "_string?" => if let Expr::String(_) = *e {true} else {false},
-            "_quote?" => if let Expr::Quote(_) = *e {true} else {false},
-            "_quasiquote?" => if let Expr::Quasiquote(_) = *e {true} else {false},
-            "_unquote" => if let Expr::Unquote(_) = *e {true} else {false},
-            _ => panic!("Unrecognized type")
-        };
-        if is_ok {
-            self.set_expr(Expr::Ident("t".to_string()))
-        } else {
-            self.set_expr(Expr::Nil)
-        }
-    }
-
-    fn eval_print_debug(&self, e:Rc<Expr>) -> Context {
-        let c = self.pre_eval_1(e);
-        println!("{:?}", c.expr.clone());
-        c.set_expr(Expr::Nil)
-    }
-        
-        
-    fn eval_list_ident(&self, ident:String, e2:Rc<Expr>) -> Context {
-        match ident.as_ref() {
-            "if" => self.eval_if(e2),
-            "_+" => self.eval_plus(e2),
-            "_-" => self.eval_sub(e2),
-            "_/" => self.eval_div(e2),
-            "_*" => self.eval_mul(e2),
-            "_=" => self.eval_equal(e2),
-            "_<" => self.eval_lt(e2),
-            "_>" => self.eval_gt(e2),
-            "_str" => self.eval_str(e2),
-            "_print" => self.eval_print(e2),
-            "_nil?" | "_lambda?" | "_integer?" | "_macro?" | "_float?" 
-                | "_ident?" | "_string?" | "_list?" | "_quote?"
-                | "_unquote?" | "_quasiquote?" => self.eval_type_check(ident.as_ref(),e2),
+            Expr::Vector(ref v) => {
+                let mut elems = vec!();
+                for e in v.borrow().clone() {
+                    self.quasiquote_elem(&e, depth, &mut elems)?;
+                }
+                Ok(self.set_expr(Expr::Vector(RefCell::new(elems))))
+            },
+            _ => Ok(self.clone())
+        }
+    }
+
+
+    fn eval_list_ident(&self, ident:String, e2:Rc<Expr>) -> Result<Step, EvalError> {
+        let c = match ident.as_ref() {
+            "if" => return self.eval_if(e2),
+            "do" | "begin" => return self.eval_do(e2),
+            "let" => return self.eval_let(e2),
+            "let*" => return self.eval_let_star(e2),
+            "letrec" | "letrec*" => return self.eval_letrec(ident.as_ref(), e2),
+            "eval" => return self.eval_eval(e2),
             "def" => self.eval_def(e2),
-            "_car" => self.eval_car(e2),
-            "_cdr" => self.eval_cdr(e2),
-            "_cons" => self.eval_cons(e2),
+            "define" => self.eval_define(e2),
+            "set!" => self.eval_set(e2),
             "lambda" => self.eval_lambda(e2),
-            "eval" => self.eval_eval(e2),
-            "print-debug" => self.eval_print_debug(e2),
             "defmacro" => self.eval_defmacro(e2),
+            "syntax-rules" => self.eval_syntax_rules(e2),
+            "define-syntax" => self.eval_define_syntax(e2),
+            "let-syntax" | "letrec-syntax" => return self.eval_let_syntax(ident.as_ref(), e2),
+            "guard" => return self.eval_guard(e2),
+            "include" => return Ok(Step::TailCall(self.set_rcexpr(self.include(&e2)?))),
+            "define-library" => self.eval_define_library(e2),
+            "import" => self.eval_import(e2),
             _ => {
-                let c = self.lookup(&ident);
-                if c.error {
-                    c
-                } else {
-                    self.eval_list (c.expr, e2)
-                }
+                let c = self.lookup(&ident)?;
+                return self.eval_list (c.expr, e2);
             }
-        }
+        };
+        c.map(Step::Value)
     }
 
-    fn eval_list(&self, e1:Rc<Expr>,e2:Rc<Expr>) -> Context {
+    fn eval_list(&self, e1:Rc<Expr>,e2:Rc<Expr>) -> Result<Step, EvalError> {
         match *e1 {
             Expr::Ident(ref str) => self.eval_list_ident(str.clone(),e2),
-            Expr::Lambda(ref name, ref args, ref body, ref env) => self.eval_fncall (name.clone(), args.clone(), body.clone(), e2.clone(), env),
+            Expr::Alias(ref s, ref env) => {
+                let c = self.lookup_in(env, s)?;
+                self.eval_list(c.expr, e2)
+            },
+            Expr::Lambda(_, _, _, _) => self.eval_fncall (e1.clone(), e2.clone(), false),
+            Expr::Primitive(ref p) => {
+                let args = self.eval_args(e2)?;
+                let res = p.call(self, &args)?;
+                Ok(Step::Value(self.set_rcexpr(res)))
+            },
             Expr::Cons(_,_) => {
                 let mut c = self.clone();
                 c.expr = e1.clone();
-                let c = c.eval();
-                let e = c.expr.clone();
-                c.eval_list(e,e2)
+                let c = c.eval()?;
+                self.eval_list(c.expr, e2)
+            },
+            // macros that were not expanded before evaluation, e.g. because
+            // they were defined by the same top-level form
+            Expr::Macro(_, _, _) | Expr::SyntaxRules(_, _) => {
+                let e = self.expand_macro(&e1, e2)?;
+                Ok(Step::TailCall(self.set_rcexpr(e)))
+            },
+            _ => Err(EvalError::NotAFunction(self.expr.clone()))
+        }
+    }
+
+    /// Returns a number never returned before by this context (or the ones
+    /// of the same interpreter)
+    pub fn fresh_id(&self) -> usize {
+        let id = self.shared.fresh_ids.get() + 1;
+        self.shared.fresh_ids.set(id);
+        id
+    }
+
+    // Expansion of a use of a macro (a defmacro or syntax-rules one), given
+    // its arguments. The current expression is the form using it. The body
+    // of a defmacro is evaluated in the environment the macro was defined
+    // in, and a keyword of a syntax-rules template naming a macro there is
+    // replaced by this macro if it has another meaning where it is used.
+    fn expand_macro (&self, m:&Rc<Expr>, args:Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        let res = match **m {
+            Expr::Macro(ref args_name, ref body, ref env) => {
+                let mut c = self.new_frame(env);
+                c.global_env = env.clone();
+                let mut c = c.eval_fn_args("macro", args_name.clone(), args, true, self)?;
+                c.expr = body.clone();
+                c.eval()?.expr
+            },
+            Expr::SyntaxRules(ref s, ref env) => {
+                // the free identifiers of the template refer to their
+                // binding where the macro was defined
+                let resolve = |name:&str| env.lookup(name).map(|_| Rc::new(Expr::Alias(name.to_string(), env.clone())));
+                match s.expand(&args, &format!("~{}", self.fresh_id()), &resolve) {
+                    Some(e) => e,
+                    None => {
+                        let keyword = match *self.expr {
+                            Expr::Cons(ref k, _) => match **k {
+                                Expr::Ident(ref k) => k.clone(),
+                                _ => "syntax-rules".to_string()
+                            },
+                            _ => "syntax-rules".to_string()
+                        };
+                        return Err(self.bad_form(&keyword));
+                    }
+                }
+            },
+            _ => return Err(EvalError::NotAFunction(m.clone()))
+        };
+        info!("Debug: macroexpand gives\n{}", res);
+        // errors in the expansion are reported at the position of the form
+        let span = self.shared.sources.borrow().get(&self.expr).cloned();
+        if let Some(span) = span {
+            let mut sources = self.shared.sources.borrow_mut();
+            if sources.get(&res).is_none() {
+                sources.insert(&res, span);
+            }
+        }
+        Ok(res)
+    }
+
+    // Expand form once if it is the use of a macro bound in the environment,
+    // or of a macro put in the form (or an alias to it) by the expansion of
+    // another one
+    fn expand_1 (&self, form:&Rc<Expr>) -> Result<Option<Rc<Expr>>, EvalError> {
+        if let Expr::Cons(ref head, ref args) = **form {
+            let m = match **head {
+                Expr::Ident(ref s) if !is_reserved_ident(s) => self.env.lookup(s),
+                Expr::Alias(ref s, ref env) => env.lookup(s),
+                Expr::Macro(_, _, _) | Expr::SyntaxRules(_, _) => Some(head.clone()),
+                _ => None
+            };
+            if let Some(m) = m {
+                if let Expr::Macro(_, _, _) | Expr::SyntaxRules(_, _) = *m {
+                    return self.set_rcexpr(form.clone()).expand_macro(&m, args.clone()).map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Expands form once if it is the use of a macro, and returns it
+    /// unchanged otherwise
+    pub fn macroexpand_1 (&self, form:&Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        Ok(self.expand_1(form)?.unwrap_or_else(|| form.clone()))
+    }
+
+    /// Expands form until it is no longer the use of a macro; its subforms
+    /// are not expanded
+    pub fn macroexpand (&self, form:&Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        let mut form = form.clone();
+        while let Some(e) = self.expand_1(&form)? {
+            form = e;
+        }
+        Ok(form)
+    }
+
+    // Apply f to the elements of a list (with their index), returning the
+    // list itself if no element changed
+    fn map_elems<F> (&self, l:&Rc<Expr>, mut f:F) -> Result<Rc<Expr>, EvalError>
+        where F: FnMut(usize, &Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        let mut elems = vec!();
+        let mut changed = false;
+        let mut tail = l.clone();
+        while let Expr::Cons(ref car, ref cdr) = *tail.clone() {
+            let e = f(elems.len(), car)?;
+            changed = changed || !Rc::ptr_eq(&e, car);
+            elems.push(e);
+            tail = cdr.clone();
+        }
+        if !changed {
+            return Ok(l.clone());
+        }
+        let mut res = tail;
+        for e in elems.into_iter().rev() {
+            res = Rc::new(Expr::Cons(e, res));
+        }
+        let span = self.shared.sources.borrow().get(l).cloned();
+        if let Some(span) = span {
+            self.shared.sources.borrow_mut().insert(&res, span);
+        }
+        Ok(res)
+    }
+
+    // Expand the elements of a list from the index start
+    fn expand_from (&self, l:&Rc<Expr>, start:usize, bound:&Bound) -> Result<Rc<Expr>, EvalError> {
+        self.map_elems(l, |i, e| if i >= start {self.expand_in(e, bound)} else {Ok(e.clone())})
+    }
+
+    /// Expands the macros used by an expression and its subforms, so that
+    /// they are expanded once rather than each time the code is evaluated.
+    ///
+    /// Only the macros bound when the expression is expanded are; the
+    /// others are expanded during evaluation.
+    pub fn expand (&self, e:&Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        self.expand_in(e, &Bound::new())
+    }
+
+    // Expand e, in which the names of bound are bound by the forms around
+    // it: a form whose head is one of them is not the use of a macro
+    fn expand_in (&self, e:&Rc<Expr>, bound:&Bound) -> Result<Rc<Expr>, EvalError> {
+        let mut e = e.clone();
+        while !is_shadowed(&e, bound) {
+            match self.expand_1(&e)? {
+                Some(e2) => e = e2,
+                None => break
+            }
+        }
+        let (head, args) = match *e {
+            Expr::Cons(ref head, ref args) => (head, args),
+            Expr::Quasiquote(ref q) => {
+                let q2 = self.expand_quasiquote(q, 0, bound)?;
+                return Ok(if Rc::ptr_eq(q, &q2) {e.clone()} else {Rc::new(Expr::Quasiquote(q2))});
+            },
+            _ => return Ok(e.clone())
+        };
+        let keyword = match **head {
+            Expr::Ident(ref s) if !bound.contains(s) => s.as_str(),
+            _ => return self.expand_from(&e, 0, bound)
+        };
+        let named = match **args {
+            Expr::Cons(ref first, _) => matches!(**first, Expr::Ident(_)),
+            _ => false
+        };
+        match keyword {
+            // (guard (var clause...) body...)
+            "guard" => self.map_elems(&e, |i, x| match i {
+                0 => Ok(x.clone()),
+                1 => {
+                    let inner = bind(bound, nth(x, 0).and_then(|v| ident_name(&v)));
+                    self.map_elems(x, |j, clause| if j == 0 {Ok(clause.clone())} else {self.expand_from(clause, 0, &inner)})
+                },
+                _ => self.expand_in(x, bound)
+            }),
+            "include" => self.include(args),
+            // macro definitions are left as they are
+            "defmacro" | "syntax-rules" | "define-syntax" | "let-syntax" | "letrec-syntax" => Ok(e.clone()),
+            // libraries are expanded in their own environment
+            "define-library" | "import" => Ok(e.clone()),
+            // (lambda [name] (args...) body...)
+            "lambda" => {
+                let params = if named {2} else {1};
+                let mut names = vec!();
+                if named {
+                    names.extend(nth(&e, 1).and_then(|n| ident_name(&n)));
+                }
+                if let Some(p) = nth(&e, params) {
+                    param_names(&p, &mut names);
+                }
+                defined_names(&e, params + 1, &mut names);
+                self.expand_from(&e, params + 1, &bind(bound, names))
+            },
+            // (define (name args...) body...), or the name of the variable
+            "define" | "def" => match nth(&e, 1) {
+                Some(ref head) if matches!(**head, Expr::Cons(_, _)) => {
+                    let mut names = vec!();
+                    if let Expr::Cons(_, ref params) = **head {
+                        param_names(params, &mut names);
+                    }
+                    defined_names(&e, 2, &mut names);
+                    self.expand_from(&e, 2, &bind(bound, names))
+                },
+                _ => self.expand_from(&e, 2, bound)
+            },
+            "set!" => self.expand_from(&e, 2, bound),
+            // the bindings, after the name of a named let, and the body
+            "let" | "let*" | "letrec" | "letrec*" => {
+                let bindings = if named {2} else {1};
+                let binding_names:Vec<String> = nth(&e, bindings).map_or(vec!(), |b| {
+                    list_elems(&b).iter().filter_map(|b| nth(b, 0).and_then(|n| ident_name(&n))).collect()
+                });
+                let mut names = binding_names.clone();
+                if named {
+                    names.extend(nth(&e, 1).and_then(|n| ident_name(&n)));
+                }
+                defined_names(&e, bindings + 1, &mut names);
+                let body = bind(bound, names);
+                self.map_elems(&e, |i, x| {
+                    if i == bindings {
+                        // the inits of let* see the bindings before them,
+                        // the ones of letrec all of them
+                        self.map_elems(x, |j, b| match keyword {
+                            "let*" => self.expand_from(b, 1, &bind(bound, binding_names[..j.min(binding_names.len())].to_vec())),
+                            "letrec" | "letrec*" => self.expand_from(b, 1, &bind(bound, binding_names.clone())),
+                            _ => self.expand_from(b, 1, bound)
+                        })
+                    } else if i > bindings {
+                        self.expand_in(x, &body)
+                    } else {
+                        Ok(x.clone())
+                    }
+                })
+            },
+            _ => self.expand_from(&e, 1, bound)
+        }
+    }
+
+    // Expand the unquoted expressions of a quasiquote
+    fn expand_quasiquote (&self, e:&Rc<Expr>, depth:usize, bound:&Bound) -> Result<Rc<Expr>, EvalError> {
+        // expand x, what e contains, as code (depth is None) or as part of
+        // the quasiquote, and wrap it back with f if it changed
+        let rebuild = |x:&Rc<Expr>, depth:Option<usize>, f:fn(Rc<Expr>) -> Expr| {
+            let x2 = match depth {
+                None => self.expand_in(x, bound)?,
+                Some(depth) => self.expand_quasiquote(x, depth, bound)?
+            };
+            Ok(if Rc::ptr_eq(x, &x2) {e.clone()} else {Rc::new(f(x2))})
+        };
+        match **e {
+            Expr::Unquote(ref x) if depth == 0 => rebuild(x, None, Expr::Unquote),
+            Expr::UnquoteSplicing(ref x) if depth == 0 => rebuild(x, None, Expr::UnquoteSplicing),
+            Expr::Unquote(ref x) => rebuild(x, Some(depth - 1), Expr::Unquote),
+            Expr::UnquoteSplicing(ref x) => rebuild(x, Some(depth - 1), Expr::UnquoteSplicing),
+            Expr::Quasiquote(ref x) => rebuild(x, Some(depth + 1), Expr::Quasiquote),
+            Expr::Cons(_, _) => self.map_elems(e, |_, x| self.expand_quasiquote(x, depth, bound)),
+            _ => Ok(e.clone())
+        }
+    }
+
+    /// Calls a function (lambda or primitive) with already evaluated
+    /// arguments, returning its value
+    pub fn apply(&self, f:Rc<Expr>, args:&[Rc<Expr>]) -> Result<Rc<Expr>, EvalError> {
+        match *f {
+            Expr::Primitive(ref p) => p.call(self, args),
+            Expr::Lambda(_, _, _, _) => {
+                let mut list = Rc::new(Expr::Nil);
+                for e in args.iter().rev() {
+                    list = Rc::new(Expr::Cons(e.clone(), list));
+                }
+                match self.eval_fncall(f.clone(), list, true)? {
+                    Step::Value(c) => Ok(c.expr),
+                    Step::TailCall(c) => Ok(c.eval()?.expr)
+                }
             },
-            Expr::Macro(ref args,ref body) => self.eval_macro(args.clone(), body.clone(), e2.clone()),
-            _ => self.error_str("Invalid argument in first place of evaluated list")
+            _ => Err(EvalError::NotAFunction(f.clone()))
         }
     }
 
-    pub fn eval_expr(&self, expr:Rc<Expr>) -> Context {
+    /// Calls f with the current continuation, as call/cc does. The
+    /// continuation is escaping only: calling it returns its argument
+    /// from this call, as long as it hasn't returned yet.
+    pub fn call_cc(&self, f:Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        let id = self.fresh_id();
+        let k = Primitive::new("continuation", Arity::Range(0, 1), move |_, args| {
+            let value = args.first().cloned().unwrap_or_else(|| Rc::new(Expr::Nil));
+            Err(EvalError::Escape(id, value))
+        });
+        match self.apply(f, &[Rc::new(Expr::Primitive(k))]) {
+            Err(err) => match *err.cause() {
+                EvalError::Escape(n, ref value) if n == id => Ok(value.clone()),
+                _ => Err(err)
+            },
+            res => res
+        }
+    }
+
+    /// Calls the thunk between calls to before and after. after is called
+    /// however the thunk exits, by returning, calling a continuation or
+    /// with an error.
+    pub fn dynamic_wind(&self, before:Rc<Expr>, thunk:Rc<Expr>, after:Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        self.apply(before, &[])?;
+        let res = self.apply(thunk, &[]);
+        self.apply(after, &[])?;
+        res
+    }
+
+    // Calls f with handler installed as the current exception handler
+    fn with_handler<T, F>(&self, handler:Rc<Expr>, f:F) -> Result<T, EvalError>
+        where F: FnOnce() -> Result<T, EvalError> {
+        let n = self.shared.handlers.borrow().len();
+        self.shared.handlers.borrow_mut().push(handler);
+        let res = f();
+        self.shared.handlers.borrow_mut().truncate(n);
+        res
+    }
+
+    /// Calls the thunk with handler as the current exception handler, as
+    /// with-exception-handler does
+    pub fn with_exception_handler(&self, handler:Rc<Expr>, thunk:Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        self.with_handler(handler, || self.apply(thunk, &[]))
+    }
+
+    /// Raises obj as an exception: the current handler is called with it,
+    /// with the previous handlers installed. If the exception is
+    /// continuable, its value is returned; otherwise the handler returning
+    /// raises another exception. Without handler, the exception reaches
+    /// the caller of eval, as its error if obj is an error object.
+    pub fn raise(&self, obj:Rc<Expr>, continuable:bool) -> Result<Rc<Expr>, EvalError> {
+        let handler = match self.shared.handlers.borrow_mut().pop() {
+            Some(handler) => handler,
+            None => return Err(match *obj {
+                Expr::Error(ref err) => err.clone(),
+                _ => EvalError::Raised(obj.clone())
+            })
+        };
+        let mut res = self.apply(handler.clone(), std::slice::from_ref(&obj));
+        if res.is_ok() && !continuable {
+            let irritants = Rc::new(Expr::Cons(obj, Rc::new(Expr::Nil)));
+            let err = EvalError::Error("exception handler returned".to_string(), irritants);
+            res = self.raise(Rc::new(Expr::Error(err)), false);
+        }
+        self.shared.handlers.borrow_mut().push(handler);
+        // the handlers have already been called, they must not be again
+        // for the same error
+        res.map_err(|err| match err {
+            EvalError::Raised(_) | EvalError::Escape(_, _) => err,
+            EvalError::Located(span, err) => match *err {
+                EvalError::Raised(_) | EvalError::Escape(_, _) => EvalError::Located(span, err),
+                err => EvalError::Located(span, Box::new(EvalError::Raised(Rc::new(Expr::Error(err)))))
+            },
+            err => EvalError::Raised(Rc::new(Expr::Error(err)))
+        })
+    }
+
+    // An error of the evaluator is raised as an error object, if there
+    // is a handler to call
+    fn raise_error(&self, err:EvalError) -> EvalError {
+        match *err.cause() {
+            EvalError::Raised(_) | EvalError::Escape(_, _) => return err,
+            _ => ()
+        }
+        if self.shared.handlers.borrow().is_empty() {
+            return err;
+        }
+        match self.raise(Rc::new(Expr::Error(err)), false) {
+            Err(err) => err,
+            Ok(_) => unreachable!("non-continuable raise returned")
+        }
+    }
+
+    // (guard (var clause...) body...): evaluates the body and, if it
+    // raises an exception, the clauses (like the ones of cond) with var
+    // bound to it. It is raised again if no clause applies.
+    fn eval_guard (&self, e:Rc<Expr>) -> Result<Step, EvalError> {
+        let (var, clauses, body) = match *e {
+            Expr::Cons(ref spec, ref body) => match **spec {
+                Expr::Cons(ref var, ref clauses) => match **var {
+                    Expr::Ident(ref var) => (var.clone(), clauses.clone(), body.clone()),
+                    _ => return Err(self.bad_form("guard"))
+                },
+                _ => return Err(self.bad_form("guard"))
+            },
+            _ => return Err(self.bad_form("guard"))
+        };
+        // the handler escapes to the guard, with the raised object
+        let id = self.fresh_id();
+        let handler = Primitive::new("guard", Arity::Exactly(1), move |_, args| {
+            Err(EvalError::Escape(id, args[0].clone()))
+        });
+        let res = self.with_handler(Rc::new(Expr::Primitive(handler)), || {
+            match self.eval_do(body)? {
+                Step::Value(c) => Ok(c),
+                Step::TailCall(c) => c.eval()
+            }
+        });
+        let obj = match res {
+            Ok(c) => return Ok(Step::Value(c)),
+            Err(err) => match *err.cause() {
+                EvalError::Escape(n, ref obj) if n == id => obj.clone(),
+                _ => return Err(err)
+            }
+        };
+        let c = self.new_frame(&self.env).add_env(var, obj.clone())?;
+        let mut l = clauses;
+        loop {
+            let next = match *l {
+                Expr::Nil => break,
+                Expr::Cons(ref clause, ref next) => match **clause {
+                    Expr::Cons(ref test, ref body) => {
+                        if let Some(step) = c.eval_clause(test, body)? {
+                            return Ok(step);
+                        }
+                        next.clone()
+                    },
+                    _ => return Err(self.bad_form("guard"))
+                },
+                _ => return Err(self.bad_form("guard"))
+            };
+            l = next;
+        }
+        let res = self.raise(obj, true)?;
+        Ok(Step::Value(self.set_rcexpr(res)))
+    }
+
+    // Clause of a guard: if test is else or true, evaluates the body, or
+    // calls the function after => with the value of test
+    fn eval_clause (&self, test:&Rc<Expr>, body:&Rc<Expr>) -> Result<Option<Step>, EvalError> {
+        if matches!(**test, Expr::Ident(ref s) if s == "else") {
+            return self.eval_do(body.clone()).map(Some);
+        }
+        let value = self.set_rcexpr(test.clone()).eval()?.expr;
+        if !self.is_true(&value) {
+            return Ok(None);
+        }
+        match **body {
+            Expr::Nil => Ok(Some(Step::Value(self.set_rcexpr(value)))),
+            Expr::Cons(ref arrow, ref f) if matches!(**arrow, Expr::Ident(ref s) if s == "=>") => {
+                let f = match **f {
+                    Expr::Cons(ref f, ref r) if matches!(**r, Expr::Nil) => f.clone(),
+                    _ => return Err(self.bad_form("guard"))
+                };
+                let f = self.set_rcexpr(f).eval()?.expr;
+                let res = self.apply(f, &[value])?;
+                Ok(Some(Step::Value(self.set_rcexpr(res))))
+            },
+            _ => self.eval_do(body.clone()).map(Some)
+        }
+    }
+
+    /// Evaluates a top-level form in the global environment, after
+    /// expanding the macros it uses
+    pub fn eval_expr(&self, expr:Rc<Expr>) -> Result<Context, EvalError> {
         let mut c = self.clone();
-        c.env = Rc::new(RefCell::new(HashMap::new()));
+        c.env = c.global_env.clone();
         c.expr = expr.clone();
+        c.expr = c.expand(&expr).map_err(|err| c.locate(err))?;
         c.eval()
     }
 
-    pub fn eval_file(&self, file:&str) -> Context {
-        let es = read::read_file(file);
+    pub fn eval_file(&self, file:&str) -> Result<Context, EvalError> {
+        let es = read::read_file(file, &mut self.shared.sources.borrow_mut())?;
+        let path = fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file));
+        self.in_file(path, || {
+            let mut c = self.clone();
+            for e in es {
+                c = c.eval_expr(e.clone())?;
+            }
+            Ok(c)
+        })
+    }
+
+    // Calls f with path as the current file
+    fn in_file<T, F>(&self, path:PathBuf, f:F) -> Result<T, EvalError>
+        where F: FnOnce() -> Result<T, EvalError> {
+        let n = self.shared.files.borrow().len();
+        self.shared.files.borrow_mut().push(path);
+        let res = f();
+        self.shared.files.borrow_mut().truncate(n);
+        res
+    }
+
+    // Reads the forms of a file, whose name is relative to the directory
+    // of the current file. Loading a file that is already being loaded is
+    // an error, as it would never end.
+    fn read_forms(&self, form:&str, name:&Rc<Expr>) -> Result<(PathBuf, Vec<Rc<Expr>>), EvalError> {
+        let file = match **name {
+            Expr::String(ref s) => PathBuf::from(s),
+            _ => return Err(EvalError::TypeMismatch(form.to_string(), name.clone()))
+        };
+        let file = match self.current_dir() {
+            Some(dir) => dir.join(file),
+            None => file
+        };
+        self.read_file_forms(&file, name)
+    }
+
+    // Directory of the current file, if any
+    fn current_dir(&self) -> Option<PathBuf> {
+        self.shared.files.borrow().last().and_then(|current| current.parent()).map(PathBuf::from)
+    }
+
+    // Reads the forms of a file, given its path; name is the expression
+    // naming it, for the errors
+    fn read_file_forms(&self, file:&Path, name:&Rc<Expr>) -> Result<(PathBuf, Vec<Rc<Expr>>), EvalError> {
+        let err = |e:std::io::Error| EvalError::File(e.to_string(), name.clone());
+        let path = fs::canonicalize(file).map_err(err)?;
+        if self.shared.files.borrow().contains(&path) {
+            return Err(EvalError::File("it is already being loaded".to_string(), name.clone()));
+        }
+        let src = fs::read_to_string(&path).map_err(err)?;
+        let forms = read::read_source(&src, &file.to_string_lossy(), 1, &mut self.shared.sources.borrow_mut())?;
+        Ok((path, forms))
+    }
+
+    /// Evaluates the forms of a file in the global environment, as load
+    /// does, returning the value of the last one
+    pub fn load(&self, name:&Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        let (path, forms) = self.read_forms("load", name)?;
+        self.eval_forms(path, forms)
+    }
+
+    // Evaluates the forms read from a file in the global environment
+    fn eval_forms(&self, path:PathBuf, forms:Vec<Rc<Expr>>) -> Result<Rc<Expr>, EvalError> {
+        self.in_file(path, || {
+            let mut c = self.set_expr(Expr::Nil);
+            for e in forms {
+                c = c.eval_expr(e)?;
+            }
+            Ok(c.expr)
+        })
+    }
+
+    // (include file...): the forms of the files, expanded, in a begin
+    fn include(&self, names:&Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        let mut forms = vec!();
+        let mut l = names.clone();
+        loop {
+            let next = match *l {
+                Expr::Nil => break,
+                Expr::Cons(ref name, ref next) => {
+                    let (path, es) = self.read_forms("include", name)?;
+                    self.in_file(path, || {
+                        for e in es {
+                            forms.push(self.expand(&e)?);
+                        }
+                        Ok(())
+                    })?;
+                    next.clone()
+                },
+                _ => return Err(self.bad_form("include"))
+            };
+            l = next;
+        }
+        let mut res = Rc::new(Expr::Nil);
+        for e in forms.into_iter().rev() {
+            res = Rc::new(Expr::Cons(e, res));
+        }
+        Ok(Rc::new(Expr::Cons(Rc::new(Expr::Ident("begin".to_string())), res)))
+    }
+
+    // (define-library name declaration...): the declarations are export,
+    // import, begin and include. The library is evaluated in a new
+    // environment, extending the one of the builtins.
+    fn eval_define_library(&self, e:Rc<Expr>) -> Result<Context, EvalError> {
+        let (name, decls) = match *e {
+            Expr::Cons(ref name, ref decls) => (name.clone(), decls.clone()),
+            _ => return Err(self.bad_form("define-library"))
+        };
+        let parts = library::name_parts(&name).ok_or_else(|| self.bad_form("define-library"))?;
+        let env = Env::extend(&self.shared.base_env);
         let mut c = self.clone();
-        for e in es {
-            c = c.eval_expr(e.clone());
-            if c.error {
-                break;
+        c.env = env.clone();
+        c.global_env = env.clone();
+        let mut exports = vec!();
+        let mut l = decls;
+        loop {
+            let next = match *l {
+                Expr::Nil => break,
+                Expr::Cons(ref decl, ref next) => {
+                    let (keyword, args) = match **decl {
+                        Expr::Cons(ref keyword, ref args) => match **keyword {
+                            Expr::Ident(ref keyword) => (keyword.as_str(), args),
+                            _ => return Err(self.bad_form("define-library"))
+                        },
+                        _ => return Err(self.bad_form("define-library"))
+                    };
+                    match keyword {
+                        "export" => exports.extend(self.export_specs(args)?),
+                        "import" => c = c.eval_import(args.clone())?,
+                        "begin" => {
+                            let mut forms = args.clone();
+                            while let Expr::Cons(ref form, ref next) = *forms.clone() {
+                                c = c.eval_expr(form.clone())?;
+                                forms = next.clone();
+                            }
+                        },
+                        "include" => c = c.eval_expr(decl.clone())?,
+                        _ => return Err(self.bad_form("define-library"))
+                    }
+                    next.clone()
+                },
+                _ => return Err(self.bad_form("define-library"))
+            };
+            l = next;
+        }
+        for (_, internal) in &exports {
+            if env.lookup(internal).is_none() {
+                return Err(EvalError::UnboundVariable(Rc::new(Expr::Ident(internal.clone()))));
+            }
+        }
+        let lib = Library { env, exports };
+        self.shared.libraries.borrow_mut().insert(library::key(&parts), Rc::new(lib));
+        Ok(self.set_rcexpr(name))
+    }
+
+    // Specifications of an export declaration: names, or (rename
+    // internal external). Returns the pairs (external, internal).
+    fn export_specs(&self, specs:&Rc<Expr>) -> Result<Vec<(String, String)>, EvalError> {
+        let mut res = vec!();
+        let mut l = specs;
+        while let Expr::Cons(ref spec, ref next) = **l {
+            match **spec {
+                Expr::Ident(ref s) => res.push((s.clone(), s.clone())),
+                _ => match library::name_parts(spec).as_deref() {
+                    Some([rename, internal, external]) if rename == "rename" =>
+                        res.push((external.clone(), internal.clone())),
+                    _ => return Err(self.bad_form("export"))
+                }
+            }
+            l = next;
+        }
+        Ok(res)
+    }
+
+    // (import set...): binds the names of the import sets in the
+    // current environment
+    fn eval_import(&self, sets:Rc<Expr>) -> Result<Context, EvalError> {
+        let mut l = sets;
+        loop {
+            let next = match *l {
+                Expr::Nil => break,
+                Expr::Cons(ref spec, ref next) => {
+                    let set = ImportSet::new(spec).ok_or_else(|| self.bad_form("import"))?;
+                    let lib = self.find_library(set.library(), spec)?;
+                    let bindings = set.bindings(&lib.exports)
+                        .map_err(|reason| EvalError::Library(reason, spec.clone()))?;
+                    // the names are bound to the variables of the library,
+                    // whose changes the importers see
+                    for (name, internal) in bindings {
+                        if lib.env.lookup(&internal).is_none() {
+                            return Err(EvalError::UnboundVariable(Rc::new(Expr::Ident(internal))));
+                        }
+                        if is_reserved_ident(&name) {
+                            return Err(EvalError::ReservedIdent(Rc::new(Expr::Ident(name))));
+                        }
+                        self.env.define_alias(name, internal, lib.env.clone());
+                    }
+                    next.clone()
+                },
+                _ => return Err(self.bad_form("import"))
+            };
+            l = next;
+        }
+        Ok(self.set_expr(Expr::Nil))
+    }
+
+    // A library, loaded from the file defining it if it is not defined
+    // yet: it is looked for in the directories of the files being loaded,
+    // from the current one (or in the working directory), then in the
+    // library path
+    fn find_library(&self, parts:&[String], spec:&Rc<Expr>) -> Result<Rc<Library>, EvalError> {
+        let key = library::key(parts);
+        if let Some(lib) = self.shared.libraries.borrow().get(&key) {
+            return Ok(lib.clone());
+        }
+        let mut dirs:Vec<PathBuf> = self.shared.files.borrow().iter().rev()
+            .filter_map(|file| file.parent().map(PathBuf::from))
+            .collect();
+        if dirs.is_empty() {
+            dirs.push(PathBuf::from("."));
+        }
+        dirs.extend(self.shared.library_path.borrow().iter().cloned());
+        let file = dirs.iter().map(|dir| dir.join(library::file(parts))).find(|file| file.is_file());
+        let file = match file {
+            Some(file) => file,
+            None => return Err(EvalError::Library("library not found".to_string(), spec.clone()))
+        };
+        let name = Rc::new(Expr::String(file.to_string_lossy().into_owned()));
+        let (path, forms) = self.read_file_forms(&file, &name)?;
+        self.eval_forms(path, forms)?;
+        match self.shared.libraries.borrow().get(&key) {
+            Some(lib) => Ok(lib.clone()),
+            None => Err(EvalError::Library(format!("{} doesn't define it", file.display()), spec.clone()))
+        }
+    }
+
+    /// Evaluate the expression of the context.
+    ///
+    /// Expressions in tail position (branches of if, function bodies, last
+    /// expression of a do...) are evaluated in this loop rather than by
+    /// a recursive call, so tail calls run in constant stack space.
+    pub fn eval(&self) -> Result<Context, EvalError> {
+        let mut c = self.clone();
+        loop {
+            match c.eval_step() {
+                Ok(Step::Value(mut res)) => {
+                    res.env = self.env.clone();
+                    return Ok(res);
+                },
+                Ok(Step::TailCall(next)) => c = next,
+                Err(err) => return Err(c.raise_error(c.locate(err)))
             }
         }
-        c
     }
 
-    pub fn eval(&self) -> Context {
+    fn eval_step(&self) -> Result<Step, EvalError> {
         match *self.expr {
-            Expr::Nil => self.clone(),
-            Expr::Integer(_) => self.clone(),
-            Expr::Float(_) => self.clone(),
-            Expr::String(_) => self.clone(),
+            Expr::Nil
+                | Expr::Bool(_)
+                | Expr::Integer(_)
+                | Expr::Float(_)
+                | Expr::String(_) => Ok(Step::Value(self.clone())),
             Expr::Quote(ref e) => {
                 let mut c = self.clone();
                 c.expr = e.clone();
-                c
+                Ok(Step::Value(c))
             },
             Expr::Quasiquote(ref e) => {
                 let mut c = self.clone();
                 c.expr = e.clone();
-                c.eval_quasiquote()
+                c.eval_quasiquote(0).map(Step::Value)
             }
             Expr::Ident(ref s) => {
-                let c = self.lookup(s);
-                if c.error {
-                    c
-                } else {
-                    let e = c.expr;
-                    self.set_rcexpr(e)
-                        //c.eval()
-                }
+                let c = self.lookup(s)?;
+                Ok(Step::Value(self.set_rcexpr(c.expr)))
+            },
+            Expr::Alias(ref s, ref env) => {
+                let c = self.lookup_in(env, s)?;
+                Ok(Step::Value(self.set_rcexpr(c.expr)))
             },
             Expr::Cons(ref e1, ref e2) => self.eval_list(e1.clone(), e2.clone()),
-            _ => self.clone()
+            _ => Ok(Step::Value(self.clone()))
         }
     }
 }
-
-
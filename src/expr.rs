@@ -10,63 +10,176 @@ use std::fmt::Error;
 use std::fmt::Formatter;
 use std::fmt::Display;
 use std::rc::Rc;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::mem;
+use env::Env;
+use number;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use primitive::Primitive;
+use table::Table;
+use syntax::SyntaxRules;
+use error::EvalError;
+use port::Port;
 
 #[derive(Debug,PartialEq)]
 pub enum Expr {
     Nil,
-    Lambda(String, Rc<Expr>, Rc<Expr>, Option<HashMap<String,Rc<Expr>>>),
-    Macro(Rc<Expr>, Rc<Expr>),
+    // name (empty if anonymous), arguments, list of the forms of the body,
+    // environment it was defined in
+    Lambda(String, Rc<Expr>, Rc<Expr>, Rc<Env>),
+    // arguments, body, and environment it was defined in, for a defmacro
+    // and a syntax-rules macro
+    Macro(Rc<Expr>, Rc<Expr>, Rc<Env>),
+    SyntaxRules(SyntaxRules, Rc<Env>),
+    // identifier put in its expansion by a syntax-rules macro, which refers
+    // to its binding in the environment the macro was defined in
+    Alias(String, Rc<Env>),
+    Primitive(Primitive),
+    Bool(bool),
     Integer(i64),
+    // exact integer that doesn't fit in an i64
+    BigInt(BigInt),
+    // exact rational, whose denominator is not 1
+    Rational(BigRational),
     Float(f64),
     Ident(String),
     String(String),
+    Char(char),
     Quote(Rc<Expr>),
     Unquote(Rc<Expr>),
+    UnquoteSplicing(Rc<Expr>),
     Quasiquote(Rc<Expr>),
-    Cons(Rc<Expr>, Rc<Expr>)
+    Cons(Rc<Expr>, Rc<Expr>),
+    // mutable in place by vector-set!, so shared by all its references
+    Vector(RefCell<Vec<Rc<Expr>>>),
+    HashTable(RefCell<Table>),
+    // error object, made by error or from an error of the evaluator raised
+    // while there is an exception handler
+    Error(EvalError),
+    // input or output port, changed by reading or writing
+    Port(RefCell<Port>),
+    // object returned when reading at the end of an input port
+    Eof
 }
 
-fn fmt_inlist(e: &Expr, formatter:&mut Formatter) -> Result<(),Error> {
+// Take the cdr of a cons cell if nobody else holds it, leaving nil in its place
+fn take_cdr(e:&mut Expr) -> Option<Rc<Expr>> {
     match *e {
-        Expr::Cons(ref e1,ref e2) => {
-            try!(formatter.write_str(" "));
-            try!(e1.fmt(formatter));
-            fmt_inlist (e2, formatter)
-        },
-        Expr::Nil => formatter.write_str(")"),
-        _ => e.fmt(formatter)
+        Expr::Cons(_, ref mut cdr) if Rc::strong_count(cdr) == 1 =>
+            Some(mem::replace(cdr, Rc::new(Expr::Nil))),
+        _ => None
     }
 }
 
+// Dropping a long list recursively would overflow the stack, so the
+// cdrs that are not shared are unlinked in a loop instead
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut next = take_cdr(self);
+        while let Some(e) = next {
+            next = match Rc::try_unwrap(e) {
+                Ok(mut e) => take_cdr(&mut e),
+                Err(_) => None
+            };
+        }
+    }
+}
+
+/// Names of the characters that can be written #\name
+pub const CHAR_NAMES:&[(&str, char)] = &[
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("null", '\0'),
+    ("alarm", '\u{7}'),
+    ("backspace", '\u{8}'),
+    ("delete", '\u{7f}'),
+    ("escape", '\u{1b}')];
+
+fn char_name(c:char) -> Option<&'static str> {
+    CHAR_NAMES.iter().find(|&&(_, x)| x == c).map(|&(name, _)| name)
+}
+
 impl Display for Expr {
     fn fmt(&self, formatter:&mut Formatter) -> Result<(),Error> {
         match *self {
             Expr::Nil => formatter.write_str("()"),
             Expr::Lambda(_,_,_,_) => formatter.write_str("#Lambda"),
-            Expr::Macro(_,_) => formatter.write_str("#Macro"),
+            Expr::Macro(_,_,_) => formatter.write_str("#Macro"),
+            Expr::SyntaxRules(_, _) => formatter.write_str("#SyntaxRules"),
+            Expr::Primitive(_) => formatter.write_str("#Primitive"),
+            Expr::Bool(true) => formatter.write_str("#t"),
+            Expr::Bool(false) => formatter.write_str("#f"),
             Expr::Integer(x) => x.fmt(formatter),
-            Expr::Float(x) => x.fmt(formatter),
-            Expr::Ident(ref s) => s.fmt(formatter),
+            Expr::BigInt(ref x) => x.fmt(formatter),
+            Expr::Rational(ref x) => x.fmt(formatter),
+            Expr::Float(x) => formatter.write_str(&number::format_float(x)),
+            Expr::Ident(ref s) | Expr::Alias(ref s, _) => s.fmt(formatter),
             Expr::String(ref s) => formatter.write_fmt(format_args!("\"{}\"",
                                                                     s.clone())),
+            Expr::Char(c) => {
+                formatter.write_str("#\\")?;
+                match char_name(c) {
+                    Some(name) => formatter.write_str(name),
+                    None => c.fmt(formatter)
+                }
+            },
             Expr::Quote(ref e) => {
                 try!(formatter.write_str("'"));
                 e.fmt(formatter)
             },
             Expr::Cons(ref e1, ref e2) => {
-                try!(formatter.write_str("("));
-                try!(e1.fmt(formatter));
-                fmt_inlist(e2, formatter)
+                formatter.write_str("(")?;
+                e1.fmt(formatter)?;
+                // the elements are printed in a loop, so that long lists
+                // don't overflow the stack
+                let mut l = e2;
+                loop {
+                    match **l {
+                        Expr::Cons(ref car, ref cdr) => {
+                            formatter.write_str(" ")?;
+                            car.fmt(formatter)?;
+                            l = cdr;
+                        },
+                        Expr::Nil => break,
+                        _ => {
+                            formatter.write_str(" . ")?;
+                            l.fmt(formatter)?;
+                            break;
+                        }
+                    }
+                }
+                formatter.write_str(")")
             },
             Expr::Unquote(ref e) => {
                 try!(formatter.write_str(","));
                 e.fmt(formatter)
             },
+            Expr::UnquoteSplicing(ref e) => {
+                formatter.write_str(",@")?;
+                e.fmt(formatter)
+            },
             Expr::Quasiquote(ref e) => {
                 try!(formatter.write_str("`"));
                 e.fmt(formatter)
-            }
+            },
+            Expr::Vector(ref v) => {
+                formatter.write_str("#(")?;
+                for (i, e) in v.borrow().iter().enumerate() {
+                    if i > 0 {
+                        formatter.write_str(" ")?;
+                    }
+                    e.fmt(formatter)?;
+                }
+                formatter.write_str(")")
+            },
+            Expr::HashTable(_) => formatter.write_str("#HashTable"),
+            Expr::Error(ref err) => write!(formatter, "#Error({})", err.cause()),
+            Expr::Port(_) => formatter.write_str("#Port"),
+            Expr::Eof => formatter.write_str("#Eof")
         }
     }
 }
@@ -0,0 +1,151 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+use eval::Context;
+use expr::Expr;
+use error::EvalError;
+use primitive::Arity;
+use read;
+
+use std::rc::Rc;
+use std::path::PathBuf;
+
+/// Source of the prelude, loaded by Interpreter::new
+pub const PRELUDE:&'static str = include_str!("../data/init.scm");
+
+/// A Scheme interpreter, keeping its global environment between
+/// evaluations.
+///
+/// This is the entry point for embedding rscheme in a Rust program:
+///
+/// ```
+/// use rscheme::{Interpreter, FromExpr};
+///
+/// let mut interp = Interpreter::new();
+/// interp.set_global("x", 20).unwrap();
+/// let res = interp.eval_str("(+ x 22)").unwrap();
+/// assert_eq!(42, i64::from_expr(&res).unwrap());
+/// ```
+pub struct Interpreter {
+    context: Context
+}
+
+impl Interpreter {
+    /// Creates an interpreter with the prelude (data/init.scm) loaded
+    pub fn new() -> Interpreter {
+        let mut interp = Interpreter::without_prelude();
+        interp.eval_source(PRELUDE, "data/init.scm", 1)
+            .expect("Error in prelude");
+        interp
+    }
+
+    /// Creates an interpreter with only the builtins
+    pub fn without_prelude() -> Interpreter {
+        Interpreter {
+            context: Context::new()
+        }
+    }
+
+    /// Returns the underlying evaluation context
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Parses source code, keeping the position of expressions so errors
+    /// can be reported. line is the number of the first line of src in file.
+    /// Fails at the first syntax error.
+    pub fn read(&mut self, src:&str, file:&str, line:usize) -> Result<Vec<Rc<Expr>>, EvalError> {
+        read::read_source(src, file, line, &mut self.context.shared.sources.borrow_mut())
+    }
+
+    /// Evaluates a single (already parsed) expression
+    pub fn eval_expr(&mut self, expr:Rc<Expr>) -> Result<Rc<Expr>, EvalError> {
+        self.context = self.context.eval_expr(expr)?;
+        Ok(self.context.expr.clone())
+    }
+
+    /// Evaluates all expressions in source code, returning the value of
+    /// the last one
+    pub fn eval_source(&mut self, src:&str, file:&str, line:usize) -> Result<Rc<Expr>, EvalError> {
+        let es = self.read(src, file, line)?;
+        let mut res = Rc::new(Expr::Nil);
+        for e in es {
+            res = self.eval_expr(e)?;
+        }
+        Ok(res)
+    }
+
+    /// Evaluates all expressions in a string, returning the value of the
+    /// last one
+    pub fn eval_str(&mut self, src:&str) -> Result<Rc<Expr>, EvalError> {
+        self.eval_source(src, "<string>", 1)
+    }
+
+    /// Evaluates all expressions in a file, returning the value of the
+    /// last one
+    pub fn eval_file(&mut self, file:&str) -> Result<Rc<Expr>, EvalError> {
+        self.context = self.context.eval_file(file)?;
+        Ok(self.context.expr.clone())
+    }
+
+    /// Returns the value of a global variable, if it is defined
+    pub fn get_global(&self, name:&str) -> Option<Rc<Expr>> {
+        self.context.global_env.lookup(name)
+    }
+
+    /// Defines (or redefines) a global variable
+    pub fn set_global<T:Into<Expr>>(&mut self, name:&str, value:T) -> Result<(), EvalError> {
+        self.context.add_global(name.to_string(), Rc::new(value.into()))?;
+        Ok(())
+    }
+
+    /// Defines a global function implemented in Rust. It is called with the
+    /// values of its arguments, once their number has been checked
+    /// against arity.
+    ///
+    /// ```
+    /// use rscheme::{Interpreter, Arity, Expr, FromExpr};
+    /// use std::rc::Rc;
+    ///
+    /// let mut interp = Interpreter::new();
+    /// interp.register_fn("square", Arity::Exactly(1), |_, args| {
+    ///     let x = i64::from_expr(&args[0])?;
+    ///     Ok(Rc::new(Expr::Integer(x * x)))
+    /// });
+    /// let res = interp.eval_str("(map square '(1 2 3))").unwrap();
+    /// assert_eq!(vec!(1, 4, 9), Vec::<i64>::from_expr(&res).unwrap());
+    /// ```
+    pub fn register_fn<F>(&mut self, name:&str, arity:Arity, f:F)
+        where F: Fn(&Context, &[Rc<Expr>]) -> Result<Rc<Expr>, EvalError> + 'static {
+        self.context.define_primitive(name, arity, f);
+    }
+
+    /// Adds a directory where import looks for the files of libraries, the
+    /// file of (foo bar) being foo/bar.sld
+    pub fn add_library_path<P:Into<PathBuf>>(&mut self, dir:P) {
+        self.context.shared.library_path.borrow_mut().push(dir.into());
+    }
+
+    /// Enables or disables the legacy mode, where () is false like #f
+    pub fn set_nil_is_false(&mut self, nil_is_false:bool) {
+        self.context.nil_is_false = nil_is_false;
+    }
+
+    /// Returns a description of an error, with an excerpt of the source
+    /// code where it happened if it is known
+    pub fn report(&self, err:&EvalError) -> String {
+        self.context.report(err)
+    }
+}
+
+// The default interpreter has the prelude loaded
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
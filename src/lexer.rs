@@ -6,7 +6,16 @@
     // the Free Software Foundation; either version 2 of the License, or
     // (at your option) any later version.
 
+use source::{Span, SourceText};
+use expr::{Expr, CHAR_NAMES};
+use error::EvalError;
+use number;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+
 use std::result;
+use std::rc::Rc;
 
 pub type Result = result::Result<Token, &'static str>;
 
@@ -14,27 +23,57 @@ pub type Result = result::Result<Token, &'static str>;
 pub enum Token {
     Integer (i64),
     Float (f64),
+    BigInt (BigInt),
+    Rational (BigRational),
     Ident (String),
     String (String),
+    Bool (bool),
+    Char (char),
     OpeningParen,
+    // #( opening a vector
+    VectorParen,
     ClosingParen,
+    // . separating the car and the cdr of a pair
+    Dot,
     Quote,
     Unquote,
+    UnquoteSplicing,
     Quasiquote
 }
 
+/// A token, along with its position in the source
+#[derive(Debug,Clone)]
+pub struct Lexeme {
+    pub token: Token,
+    pub span: Span
+}
+
 pub struct Lexer<'a> {
     xs: &'a [char],
-    pub tokens: &'a mut Vec<Token>,
-    pub n_par: u32
+    pub tokens: &'a mut Vec<Lexeme>,
+    pub n_par: u32,
+    /// The first error found, after which the rest of the input is ignored
+    pub error: Option<EvalError>,
+    file: Rc<String>,
+    // the text lexed, which the spans refer to for excerpts
+    text: Rc<SourceText>,
+    line: usize,
+    col: usize,
+    start: (usize, usize)
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(v:&'a Vec<char>, t:&'a mut Vec<Token>) -> Lexer<'a> {
+    pub fn new(v:&'a Vec<char>, t:&'a mut Vec<Lexeme>) -> Lexer<'a> {
         Lexer {
             xs: v,
             n_par: 0,
+            error: None,
             tokens: t,
+            file: Rc::new("<input>".to_string()),
+            text: Rc::new(SourceText::new(v, 1)),
+            line: 1,
+            col: 1,
+            start: (1, 1)
         }
     }
 
@@ -42,9 +81,63 @@ impl<'a> Lexer<'a> {
         self.n_par = n_par;
     }
 
+    /// Sets the name of the file that is lexed, used in token spans
+    pub fn with_file(&mut self, file:&str) {
+        self.file = Rc::new(file.to_string());
+    }
+
+    /// Sets the line number of the first character
+    pub fn with_line(&mut self, line: usize) {
+        self.line = line;
+        self.text = Rc::new(SourceText::new(self.xs, line));
+    }
+
+    // Consume n characters, updating the current position
+    fn bump(&mut self, n:usize) {
+        for c in &self.xs[0..n] {
+            if *c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
+        self.xs = &self.xs[n..];
+    }
+
+    // Span from self.start to the current position
+    fn span(&self) -> Span {
+        Span {
+            file: self.file.clone(),
+            text: self.text.clone(),
+            line: self.start.0,
+            col: self.start.1,
+            end_line: self.line,
+            end_col: self.col
+        }
+    }
+
+    // Push a token that started at self.start and ends at current position
+    fn push(&mut self, token:Token) {
+        let span = self.span();
+        self.tokens.push(Lexeme {
+            token: token,
+            span: span
+        });
+    }
+
+    // Record an error about the text from self.start to the current
+    // position, and stop lexing
+    fn fail(&mut self, reason:String) {
+        if self.error.is_none() {
+            self.error = Some(EvalError::Syntax(reason, self.span()));
+        }
+        self.xs = &[];
+    }
+
     // Return the number of par (0 if balanced)
     // and fill the tokens vector
-    pub fn tokenize(&'a mut self) -> u32 {
+    pub fn tokenize(&mut self) -> u32 {
         loop {
             if self.xs.len() == 0 {
                 return self.n_par;
@@ -59,60 +152,62 @@ impl<'a> Lexer<'a> {
         if !self.xs.is_empty() {
             let c = self.xs[0];
             if c.is_whitespace() {
-                self.xs = &self.xs[1..];
+                self.bump(1);
                 self.read_single_token();
             } else {
+                self.start = (self.line, self.col);
                 match c {
                     '(' => {
-                        self.tokens.push(Token::OpeningParen);
+                        self.bump(1);
+                        self.push(Token::OpeningParen);
                         self.n_par += 1;
-                        self.xs = &self.xs[1..];
                     },
                     ')' => {
                         if self.n_par > 0 {
-                            self.tokens.push(Token::ClosingParen);
+                            self.bump(1);
+                            self.push(Token::ClosingParen);
                             self.n_par -= 1;
-                            self.xs = &self.xs[1..];
                         } else {
-                            error!("Mismatched parenthesis: too many )s");
-                            self.xs = &[];
+                            self.bump(1);
+                            self.fail("mismatched parenthesis: too many )s".to_string());
                         }
                     },
                     '\\' => {
-                        self.tokens.push(Token::Quote);
-                        self.xs = &self.xs[1..];
+                        self.bump(1);
+                        self.push(Token::Quote);
                     },
                     '`' => {
-                        self.tokens.push(Token::Quasiquote);
-                        self.xs = &self.xs[1..];
+                        self.bump(1);
+                        self.push(Token::Quasiquote);
                     },
                     '\'' => {
-                        self.tokens.push(Token::Quote);
-                        self.xs = &self.xs[1..];
+                        self.bump(1);
+                        self.push(Token::Quote);
                     },
                     ',' => {
-                        self.tokens.push(Token::Unquote);
-                        self.xs = &self.xs[1..];
+                        if self.xs.get(1) == Some(&'@') {
+                            self.bump(2);
+                            self.push(Token::UnquoteSplicing);
+                        } else {
+                            self.bump(1);
+                            self.push(Token::Unquote);
+                        }
                     },
                     '"' => {
-                        self.xs = &self.xs[1..];
+                        self.bump(1);
                         let mut s = String::new();
                         self.read_string(&mut s);
                     },
-                    '0' ... '9' => {
-                        let mut s = String::new();
-                        self.read_number(&mut s,0);
-                    },
-                    '.' => {
-                        let mut s = String::new();
-                        s.push('.');
-                        self.xs = &self.xs[1..];
-                        self.read_number(&mut s,1);
-                    },
+                    '.' => self.read_dot(),
+                    '0' ... '9' => self.read_number(),
                     ';' => {
-                        self.xs = &self.xs[1..];
+                        self.bump(1);
                         self.read_comment();
                     },
+                    '#' => {
+                        let mut s = String::new();
+                        self.read_hash(&mut s);
+                    },
                     _ => {
                         let mut s = String::new();
                         self.read_ident(&mut s);
@@ -122,79 +217,75 @@ impl<'a> Lexer<'a> {
         }
     }
     
-    fn finish_number(&mut self, s:&String, n_dot:u8) {
-        if n_dot == 0 { // integer
-            match s.parse::<i64>() {
-                Ok(x) => self.tokens.push(Token::Integer(x)),
-                Err(_) => {
-                    error!("Error parsing 'integer': {}", s.clone());
-                    self.xs = &[];
-                }
-            }
-        } else { // float
-            match s.parse::<f64>() {
-                Ok(x) => self.tokens.push(Token::Float(x)),
-                Err(_) => {
-                    error!("Error parsing 'float': {}", s.clone());
-                    self.xs = &[];
-                }
+    // Consume characters up to a delimiter, appending them to s
+    fn read_word(&mut self, s:&mut String) {
+        while !self.xs.is_empty() {
+            let c = self.xs[0];
+            if c.is_whitespace() || c == '(' || c == ')' || c == ';' || c == '"' {
+                break;
             }
+            s.push(c);
+            self.bump(1);
         }
     }
-    
-    fn read_number(&mut self,s:&mut String, n_dot:u8) {
-        if self.xs.len() == 0 {
-            self.finish_number(s, n_dot);
-            return;
-        } 
-        
-        let c = self.xs[0];
-        if c.is_whitespace() {
-            self.xs = &self.xs[1..];
-            self.finish_number(s, n_dot);
-            return;
+
+    fn push_number(&mut self, n:&Expr) {
+        let token = match *n {
+            Expr::Integer(x) => Token::Integer(x),
+            Expr::Float(x) => Token::Float(x),
+            Expr::BigInt(ref x) => Token::BigInt(x.clone()),
+            Expr::Rational(ref x) => Token::Rational(x.clone()),
+            _ => unreachable!()
+        };
+        self.push(token);
+    }
+
+    fn read_number(&mut self) {
+        let mut s = String::new();
+        self.read_word(&mut s);
+        match number::parse(&s) {
+            Some(n) => self.push_number(&n),
+            None => self.fail(format!("invalid number: {}", s))
         }
+    }
 
-        match c {
-            '0' ... '9' => {
-                s.push(c);
-                self.xs = &self.xs[1..];
-                self.read_number(s, n_dot);
-            }
-            '.' => {
-                if n_dot == 0 {
-                    s.push('.');
-                    self.xs = &self.xs[1..];
-                    self.read_number(s,1);
-                } else {
-                    error!("Lexer: Invalid number: contains more than one dot");
-                    self.xs = &[];
-                }
+    // Read a dot alone, a number starting with a dot (.5) or an identifier
+    // (...)
+    fn read_dot(&mut self) {
+        match self.xs.get(1) {
+            None => {
+                self.bump(1);
+                self.push(Token::Dot);
+            },
+            Some(&c) if c.is_whitespace() || c == '(' || c == ')' || c == ';' || c == '"' => {
+                self.bump(1);
+                self.push(Token::Dot);
             },
-            '('|')'|';' => self.finish_number(s,n_dot),
+            Some(c) if c.is_ascii_digit() => self.read_number(),
             _ => {
-                error!("Lexer: Invalid character in a number: {}", c);
-                self.xs = &[];
+                let mut s = String::new();
+                self.read_ident(&mut s);
             }
         }
     }
-        
+
     fn read_string(&mut self,s:&mut String) {
         if self.xs.len() == 0 {
-                error!("Lexer error: can't find closing quote");
+            self.fail("can't find closing quote".to_string());
         } else {
             let c = self.xs[0];
             match c {
                 '"' => {
-                    self.tokens.push(Token::String(s.clone()));
-                    self.xs = &self.xs[1..];
+                    self.bump(1);
+                    self.push(Token::String(s.clone()));
                 },
                 '\\' => {
                     if self.xs.len() == 1 {
-                        error!("Lexer error: can't finish lexing string");
+                        self.bump(1);
+                        self.fail("can't find closing quote".to_string());
                     } else {
                         let c2 = self.xs[1];
-                        self.xs = &self.xs[2..];
+                        self.bump(2);
                         match c2 {
                             '\\' => {
                                 s.push('\\');
@@ -208,16 +299,13 @@ impl<'a> Lexer<'a> {
                                 s.push('\n');
                                 self.read_string(s);
                             },
-                            _ => {
-                                error!("Unrecognized escape character \\{}", c2);
-                                self.xs = &[];
-                            }
+                            _ => self.fail(format!("unrecognized escape character \\{}", c2))
                         }
                     }
                 },
                 _ => {
                     s.push(c);
-                    self.xs = &self.xs[1..];
+                    self.bump(1);
                     self.read_string(s);
                 }
             }
@@ -232,35 +320,98 @@ impl<'a> Lexer<'a> {
         if c == '\n' {
             return;
         } else {
-            self.xs = &self.xs[1..];
+            self.bump(1);
             self.read_comment();
         }
     }
         
         
     
+    // Read a character literal, after #\: a single character, its name
+    // (#\space) or its code (#\x41)
+    fn read_char(&mut self) {
+        let c = self.xs[0];
+        self.bump(1);
+        let mut s = String::new();
+        self.read_word(&mut s);
+        if s.is_empty() {
+            return self.push(Token::Char(c));
+        }
+        s.insert(0, c);
+        let named = CHAR_NAMES.iter().find(|&&(name, _)| name == s).map(|&(_, x)| x);
+        let code = if c == 'x' {
+            u32::from_str_radix(&s[1..], 16).ok().and_then(::std::char::from_u32)
+        } else {
+            None
+        };
+        match named.or(code) {
+            Some(c) => self.push(Token::Char(c)),
+            None => self.fail(format!("invalid character: #\\{}", s))
+        }
+    }
+
+    // Read a token starting with #: #t, #f, #true, #false, a character, the
+    // opening of a vector, or a number with a prefix (#x1F, #e1.5...)
+    fn read_hash(&mut self, s:&mut String) {
+        if self.xs.len() > 1 && self.xs[1] == '(' {
+            self.bump(2);
+            self.push(Token::VectorParen);
+            self.n_par += 1;
+            return;
+        }
+        if self.xs.len() > 2 && self.xs[1] == '\\' {
+            self.bump(2);
+            return self.read_char();
+        }
+        self.read_word(s);
+        match s.as_ref() {
+            "#t" | "#true" => self.push(Token::Bool(true)),
+            "#f" | "#false" => self.push(Token::Bool(false)),
+            _ => match number::parse(s) {
+                Some(n) => self.push_number(&n),
+                None => self.fail(format!("invalid # syntax: {}", s))
+            }
+        }
+    }
+
+    // Push an identifier, or a number if it is a signed one (-1, +1.5...)
+    fn push_ident(&mut self, s:&str) {
+        if s.starts_with('-') || s.starts_with('+') {
+            if let Some(n) = number::parse(s) {
+                self.push_number(&n);
+                return;
+            }
+        }
+        self.push(Token::Ident(s.to_string()));
+    }
+
     fn read_ident(&mut self, s:&mut String) {
         if self.xs.len() == 0 {
-            self.tokens.push(Token::Ident(s.clone()));
+            self.push_ident(s);
             return;
         } 
         let c = self.xs[0];
         if c.is_whitespace() {
-            self.xs = &self.xs[1..];
-            self.tokens.push(Token::Ident(s.clone()));
+            self.push_ident(s);
             return;
         }
 
         match c {
-            '(' | ')'|';' => self.tokens.push(Token::Ident(s.clone())),
+            '(' | ')'|';' => self.push_ident(s),
             _ => {
                 s.push(c);
-                self.xs = &self.xs[1..];
+                self.bump(1);
                 self.read_ident(s)
             }
         }
     }
 }
 
-
-
+/// Returns the number of parentheses left open at the end of s (0 if
+/// they are balanced)
+pub fn paren_depth(s:&str) -> u32 {
+    let vchars:Vec<char> = s.chars().collect();
+    let mut v:Vec<Lexeme> = vec!();
+    let mut l = Lexer::new(&vchars, &mut v);
+    l.tokenize()
+}
@@ -0,0 +1,46 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! A toy scheme interpreter.
+//!
+//! The `Interpreter` type is the entry point to embed it in a Rust
+//! program; the other modules give access to the lexer, the reader and
+//! the evaluator themselves.
+
+#[macro_use]
+extern crate log;
+extern crate num_bigint;
+extern crate num_rational;
+extern crate num_traits;
+extern crate num_integer;
+
+pub mod lexer;
+pub mod read;
+pub mod eval;
+pub mod expr;
+pub mod error;
+pub mod source;
+pub mod env;
+pub mod convert;
+pub mod number;
+pub mod primitive;
+pub mod table;
+pub mod syntax;
+pub mod library;
+pub mod port;
+mod builtins;
+mod interpreter;
+
+pub use interpreter::{Interpreter, PRELUDE};
+pub use expr::Expr;
+pub use error::EvalError;
+pub use convert::FromExpr;
+pub use primitive::Arity;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,191 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Libraries, defined with `define-library` and used with `import`.
+//!
+//! A library has its own environment, extending the one of the builtins,
+//! so its definitions don't clash with the ones of the program or of
+//! other libraries. Importing it binds the names it exports to its
+//! variables, so the importers see the changes the library makes to them.
+
+use expr::Expr;
+use env::Env;
+
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// A library defined by define-library
+#[derive(Debug)]
+pub struct Library {
+    pub env: Rc<Env>,
+    /// Names exported: name for the importers, and name in the library
+    pub exports: Vec<(String, String)>
+}
+
+/// Parts of the name of a library, e.g. ["rscheme", "base"] for
+/// (rscheme base). They are identifiers or integers.
+pub fn name_parts(name:&Expr) -> Option<Vec<String>> {
+    let mut parts = vec!();
+    let mut l = name;
+    loop {
+        match *l {
+            Expr::Nil => break,
+            Expr::Cons(ref part, ref next) => {
+                match **part {
+                    Expr::Ident(ref s) => parts.push(s.clone()),
+                    Expr::Integer(n) if n >= 0 => parts.push(n.to_string()),
+                    _ => return None
+                }
+                l = next;
+            },
+            _ => return None
+        }
+    }
+    if parts.is_empty() {None} else {Some(parts)}
+}
+
+/// Key of a library in the table of the defined ones
+pub fn key(parts:&[String]) -> String {
+    format!("({})", parts.join(" "))
+}
+
+/// Path of the file defining a library, relative to a directory of the
+/// search path: (rscheme base) is in rscheme/base.sld
+pub fn file(parts:&[String]) -> PathBuf {
+    let mut path:PathBuf = parts.iter().collect();
+    path.set_extension("sld");
+    path
+}
+
+/// A set of names to import, as written in an import declaration
+pub enum ImportSet {
+    /// All the exports of a library (parts of its name)
+    Library(Vec<String>),
+    Only(Box<ImportSet>, Vec<String>),
+    Except(Box<ImportSet>, Vec<String>),
+    Prefix(Box<ImportSet>, String),
+    /// Renamings: name in the set, new name
+    Rename(Box<ImportSet>, Vec<(String, String)>)
+}
+
+// Identifiers of a list
+fn idents(l:&Expr) -> Option<Vec<String>> {
+    let mut res = vec!();
+    let mut l = l;
+    while let Expr::Cons(ref e, ref next) = *l {
+        match **e {
+            Expr::Ident(ref s) => res.push(s.clone()),
+            _ => return None
+        }
+        l = next;
+    }
+    match *l {
+        Expr::Nil => Some(res),
+        _ => None
+    }
+}
+
+// Pairs of identifiers, (from to), of a list
+fn renamings(l:&Expr) -> Option<Vec<(String, String)>> {
+    let mut res = vec!();
+    let mut l = l;
+    while let Expr::Cons(ref e, ref next) = *l {
+        match idents(e)?.as_slice() {
+            [from, to] => res.push((from.clone(), to.clone())),
+            _ => return None
+        }
+        l = next;
+    }
+    match *l {
+        Expr::Nil => Some(res),
+        _ => None
+    }
+}
+
+impl ImportSet {
+    /// Parses an import set, returning None if it is ill-formed
+    pub fn new(spec:&Expr) -> Option<ImportSet> {
+        let (head, rest) = match *spec {
+            Expr::Cons(ref head, ref rest) => (head, rest),
+            _ => return None
+        };
+        let (set, args) = match **rest {
+            Expr::Cons(ref set, ref args) => (set, args),
+            _ => return name_parts(spec).map(ImportSet::Library)
+        };
+        let keyword = match **head {
+            Expr::Ident(ref s) => s.as_str(),
+            _ => return None
+        };
+        let inner = || ImportSet::new(set).map(Box::new);
+        let set = match keyword {
+            "only" => ImportSet::Only(inner()?, idents(args)?),
+            "except" => ImportSet::Except(inner()?, idents(args)?),
+            "prefix" => match idents(args)?.as_slice() {
+                [prefix] => ImportSet::Prefix(inner()?, prefix.clone()),
+                _ => return None
+            },
+            "rename" => ImportSet::Rename(inner()?, renamings(args)?),
+            _ => ImportSet::Library(name_parts(spec)?)
+        };
+        Some(set)
+    }
+
+    /// Parts of the name of the library the names are imported from
+    pub fn library(&self) -> &[String] {
+        match *self {
+            ImportSet::Library(ref parts) => parts,
+            ImportSet::Only(ref set, _)
+                | ImportSet::Except(ref set, _)
+                | ImportSet::Prefix(ref set, _)
+                | ImportSet::Rename(ref set, _) => set.library()
+        }
+    }
+
+    /// The names imported, given the exports of the library: name in
+    /// the importer, and name in the library. Returns an error message if
+    /// a name is not in the set it is taken from.
+    pub fn bindings(&self, exports:&[(String, String)]) -> Result<Vec<(String, String)>, String> {
+        let check = |set:&[(String, String)], names:&mut dyn Iterator<Item=&String>| {
+            for name in names {
+                if !set.iter().any(|(n, _)| n == name) {
+                    return Err(format!("{} is not imported", name));
+                }
+            }
+            Ok(())
+        };
+        match *self {
+            ImportSet::Library(_) => Ok(exports.to_vec()),
+            ImportSet::Only(ref set, ref names) => {
+                let set = set.bindings(exports)?;
+                check(&set, &mut names.iter())?;
+                Ok(set.into_iter().filter(|(n, _)| names.contains(n)).collect())
+            },
+            ImportSet::Except(ref set, ref names) => {
+                let set = set.bindings(exports)?;
+                check(&set, &mut names.iter())?;
+                Ok(set.into_iter().filter(|(n, _)| !names.contains(n)).collect())
+            },
+            ImportSet::Prefix(ref set, ref prefix) => {
+                Ok(set.bindings(exports)?.into_iter()
+                   .map(|(n, internal)| (format!("{}{}", prefix, n), internal))
+                   .collect())
+            },
+            ImportSet::Rename(ref set, ref renamings) => {
+                let set = set.bindings(exports)?;
+                check(&set, &mut renamings.iter().map(|(from, _)| from))?;
+                Ok(set.into_iter().map(|(n, internal)| {
+                    match renamings.iter().find(|(from, _)| *from == n) {
+                        Some((_, to)) => (to.clone(), internal),
+                        None => (n, internal)
+                    }
+                }).collect())
+            }
+        }
+    }
+}
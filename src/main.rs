@@ -8,33 +8,28 @@
 
 #[macro_use]
 extern crate log;
+extern crate rscheme;
 
-mod lexer;
-mod read;
-mod eval;
-mod expr;
 mod init;
 
-#[cfg(test)]
-mod tests;
-
-use lexer::Token;
-use lexer::Lexer;
+use rscheme::Interpreter;
+use rscheme::lexer;
 
 use std::io::{self,BufRead};
 use std::io::Write;
 
 fn repl() {
-    let mut c = eval::Context::new();
-    c = c.eval_file("data/init.scm");
+    let mut interp = Interpreter::new();
 
     let stdin = io::stdin();
     let mut stdout = io::stdout();
-    
-    let mut tokens:Vec<Token> = vec!();
-    let mut n_par = 0;
+
+    // Input is accumulated until parentheses are balanced
+    let mut input = String::new();
+    let mut first_line = 1;
+    let mut n_line = 1;
     loop {
-        if n_par == 0 {
+        if input.is_empty() {
             print!("=> ");
             let r = stdout.flush();
             match r {
@@ -45,31 +40,41 @@ fn repl() {
             }
             }
         }
-        
-        let mut line = String::new();
-        stdin.lock().read_line(&mut line).unwrap();
-        let cs = line.chars().collect();
-        {
-            let mut l = Lexer::new(&cs,&mut tokens);
-            l.with_n_par(n_par);
-            n_par = l.tokenize();
 
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break, // end of input
+            Ok(_) => (),
+            Err(_) => {
+                error!("Error reading stdin. abort");
+                break;
+            }
         }
-        if n_par == 0 {
-            let es = read::read(&tokens);
-            tokens = vec!();
+        input.push_str(&line);
+        n_line += 1;
+        if lexer::paren_depth(&input) == 0 {
+            let es = interp.read(&input, "<stdin>", first_line);
+            input.clear();
+            first_line = n_line;
+            let es = match es {
+                Ok(es) => es,
+                Err(err) => {
+                    error!("{}", interp.report(&err));
+                    continue;
+                }
+            };
             for e in es {
-                c = c.eval_expr(e.clone());
-                if c.error {
-                    c.error = false;
-                    break;
-                } else {
-                    println!("{}", c.expr);
-                    info!("{:?}", c.env)
+                match interp.eval_expr(e) {
+                    Ok(res) => {
+                        println!("{}", res);
+                        info!("{:?}", interp.context().env)
+                    },
+                    Err(err) => {
+                        error!("{}", interp.report(&err));
+                        break;
+                    }
                 }
             }
-        } else {
-            continue;
         }
     }
 }
@@ -78,4 +83,3 @@ fn main() {
     init::init();
     repl();
 }
-
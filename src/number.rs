@@ -0,0 +1,324 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! The numeric tower: exact integers (`Integer`, or `BigInt` when they
+//! don't fit in an i64), exact rationals and inexact (float) numbers.
+//!
+//! Exact results are always normalized: a rational with a denominator
+//! of 1 is an integer, and an integer fitting in an i64 is an `Integer`.
+//! An operation involving an inexact number gives an inexact result.
+
+use expr::Expr;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Zero, ToPrimitive, Num as NumTrait};
+use num_integer::Integer;
+
+use std::cmp::Ordering;
+
+/// Error of an arithmetic operation
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum NumError {
+    /// The argument (0 for the first, 1 for the second) is not a number
+    NotANumber(usize),
+    DivisionByZero
+}
+
+// A number, promoted to the representation used for computing
+enum Num {
+    Exact(BigRational),
+    Inexact(f64)
+}
+
+fn to_num(e:&Expr) -> Option<Num> {
+    match *e {
+        Expr::Integer(x) => Some(Num::Exact(BigRational::from_integer(BigInt::from(x)))),
+        Expr::BigInt(ref x) => Some(Num::Exact(BigRational::from_integer(x.clone()))),
+        Expr::Rational(ref x) => Some(Num::Exact(x.clone())),
+        Expr::Float(x) => Some(Num::Inexact(x)),
+        _ => None
+    }
+}
+
+fn num_to_f64(x:&Num) -> f64 {
+    match *x {
+        Num::Exact(ref r) => r.to_f64().unwrap_or(f64::NAN),
+        Num::Inexact(f) => f
+    }
+}
+
+/// Returns an exact integer, as an Integer if it fits in an i64
+pub fn from_bigint(x:BigInt) -> Expr {
+    match x.to_i64() {
+        Some(i) => Expr::Integer(i),
+        None => Expr::BigInt(x)
+    }
+}
+
+/// Returns an exact number, as an integer if its denominator is 1
+pub fn from_rational(x:BigRational) -> Expr {
+    if x.is_integer() {
+        from_bigint(x.to_integer())
+    } else {
+        Expr::Rational(x)
+    }
+}
+
+fn from_num(x:Num) -> Expr {
+    match x {
+        Num::Exact(r) => from_rational(r),
+        Num::Inexact(f) => Expr::Float(f)
+    }
+}
+
+pub fn is_number(e:&Expr) -> bool {
+    matches!(*e, Expr::Integer(_) | Expr::BigInt(_) | Expr::Rational(_) | Expr::Float(_))
+}
+
+pub fn is_exact(e:&Expr) -> bool {
+    matches!(*e, Expr::Integer(_) | Expr::BigInt(_) | Expr::Rational(_))
+}
+
+// Promote both arguments, or tell which one is not a number
+fn promote(x:&Expr, y:&Expr) -> Result<(Num, Num), NumError> {
+    match (to_num(x), to_num(y)) {
+        (Some(x), Some(y)) => Ok((x, y)),
+        (None, _) => Err(NumError::NotANumber(0)),
+        (_, None) => Err(NumError::NotANumber(1))
+    }
+}
+
+// Apply an operation on exact numbers if both are exact, and on floats
+// otherwise
+fn arith(x:&Expr, y:&Expr,
+         fi:fn(i64, i64) -> Option<i64>,
+         fr:fn(BigRational, BigRational) -> BigRational,
+         ff:fn(f64, f64) -> f64) -> Result<Expr, NumError> {
+    // fast path for the common case
+    if let (&Expr::Integer(x1), &Expr::Integer(x2)) = (x, y) {
+        if let Some(res) = fi(x1, x2) {
+            return Ok(Expr::Integer(res));
+        }
+    }
+    let res = match promote(x, y)? {
+        (Num::Exact(x1), Num::Exact(x2)) => Num::Exact(fr(x1, x2)),
+        (x1, x2) => Num::Inexact(ff(num_to_f64(&x1), num_to_f64(&x2)))
+    };
+    Ok(from_num(res))
+}
+
+pub fn add(x:&Expr, y:&Expr) -> Result<Expr, NumError> {
+    arith(x, y, |a, b| a.checked_add(b), |a, b| a + b, |a, b| a + b)
+}
+
+pub fn sub(x:&Expr, y:&Expr) -> Result<Expr, NumError> {
+    arith(x, y, |a, b| a.checked_sub(b), |a, b| a - b, |a, b| a - b)
+}
+
+pub fn mul(x:&Expr, y:&Expr) -> Result<Expr, NumError> {
+    arith(x, y, |a, b| a.checked_mul(b), |a, b| a * b, |a, b| a * b)
+}
+
+/// Division of exact numbers is exact: (/ 1 3) is 1/3. Dividing an exact
+/// number by an exact zero is an error.
+pub fn div(x:&Expr, y:&Expr) -> Result<Expr, NumError> {
+    // exact numbers are normalized, so an exact zero is an Integer
+    if is_exact(x) && *y == Expr::Integer(0) {
+        return Err(NumError::DivisionByZero);
+    }
+    arith(x, y,
+          |a, b| if b != 0 && a.checked_rem(b) == Some(0) {a.checked_div(b)} else {None},
+          |a, b| a / b,
+          |a, b| a / b)
+}
+
+/// Numeric comparison; None if a float is NaN
+pub fn compare(x:&Expr, y:&Expr) -> Result<Option<Ordering>, NumError> {
+    if let (&Expr::Integer(x1), &Expr::Integer(x2)) = (x, y) {
+        return Ok(Some(x1.cmp(&x2)));
+    }
+    match promote(x, y)? {
+        (Num::Exact(x1), Num::Exact(x2)) => Ok(Some(x1.cmp(&x2))),
+        (x1, x2) => Ok(num_to_f64(&x1).partial_cmp(&num_to_f64(&x2)))
+    }
+}
+
+// Integer division of exact integers, with the given rounding
+fn integer_div(x:&Expr, y:&Expr, f:fn(&BigInt, &BigInt) -> BigInt) -> Result<Expr, NumError> {
+    let to_int = |e:&Expr| match *e {
+        Expr::Integer(i) => Some(BigInt::from(i)),
+        Expr::BigInt(ref i) => Some(i.clone()),
+        _ => None
+    };
+    match (to_int(x), to_int(y)) {
+        (Some(_), Some(ref b)) if b.is_zero() => Err(NumError::DivisionByZero),
+        (Some(a), Some(b)) => Ok(from_bigint(f(&a, &b))),
+        (None, _) => Err(NumError::NotANumber(0)),
+        (_, None) => Err(NumError::NotANumber(1))
+    }
+}
+
+/// Quotient of the division of two integers, truncated toward zero
+pub fn quotient(x:&Expr, y:&Expr) -> Result<Expr, NumError> {
+    integer_div(x, y, |a, b| a / b)
+}
+
+/// Remainder of the division of two integers, of the sign of x
+pub fn remainder(x:&Expr, y:&Expr) -> Result<Expr, NumError> {
+    integer_div(x, y, |a, b| a % b)
+}
+
+/// Remainder of the division of two integers, of the sign of y
+pub fn modulo(x:&Expr, y:&Expr) -> Result<Expr, NumError> {
+    integer_div(x, y, |a, b| a.mod_floor(b))
+}
+
+pub fn numerator(x:&Expr) -> Option<Expr> {
+    match *x {
+        Expr::Integer(i) => Some(Expr::Integer(i)),
+        Expr::BigInt(ref i) => Some(Expr::BigInt(i.clone())),
+        Expr::Rational(ref r) => Some(from_bigint(r.numer().clone())),
+        _ => None
+    }
+}
+
+pub fn denominator(x:&Expr) -> Option<Expr> {
+    match *x {
+        Expr::Integer(_) | Expr::BigInt(_) => Some(Expr::Integer(1)),
+        Expr::Rational(ref r) => Some(from_bigint(r.denom().clone())),
+        _ => None
+    }
+}
+
+/// Converts a number to an inexact one
+pub fn to_inexact(x:&Expr) -> Option<Expr> {
+    to_num(x).map(|n| Expr::Float(num_to_f64(&n)))
+}
+
+/// Converts a number to an exact one; None if it is not a number, or an
+/// infinite or NaN float
+pub fn to_exact(x:&Expr) -> Option<Expr> {
+    match *x {
+        Expr::Float(f) => BigRational::from_float(f).map(from_rational),
+        _ if is_exact(x) => Some(from_num(to_num(x)?)),
+        _ => None
+    }
+}
+
+// Largest power of ten by which an exact decimal is scaled; a number
+// needing more, like #e1e999999999, is invalid rather than taking forever
+// to compute
+const MAX_EXACT_SCALE:u32 = 10000;
+
+// Parse a decimal number (with an optional fraction and exponent) exactly
+fn parse_exact_decimal(s:&str) -> Option<BigRational> {
+    let (mantissa, exp) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], s[i + 1..].parse::<i32>().ok()?),
+        None => (s, 0)
+    };
+    let (int_part, frac_part) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, "")
+    };
+    let (negative, int_part) = match int_part.chars().next() {
+        Some('-') => (true, &int_part[1..]),
+        Some('+') => (false, &int_part[1..]),
+        _ => (false, int_part)
+    };
+    let digits = format!("{}{}", int_part, frac_part);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut res = BigRational::from_integer(BigInt::parse_bytes(digits.as_bytes(), 10)?);
+    let scale = exp as i64 - frac_part.len() as i64;
+    if scale.unsigned_abs() > MAX_EXACT_SCALE as u64 {
+        return None;
+    }
+    let factor = BigRational::from_integer(BigInt::from(10).pow(scale.unsigned_abs() as u32));
+    res = if scale > 0 {res * factor} else {res / factor};
+    Some(if negative {-res} else {res})
+}
+
+// Parse a number without prefix
+fn parse_real(s:&str, radix:u32, exact:bool) -> Option<Expr> {
+    match s {
+        "" => return None,
+        _ if s.contains('_') => return None,
+        "+inf.0" => return Some(Expr::Float(f64::INFINITY)),
+        "-inf.0" => return Some(Expr::Float(f64::NEG_INFINITY)),
+        "+nan.0" | "-nan.0" => return Some(Expr::Float(f64::NAN)),
+        _ => ()
+    }
+    if let Some(i) = s.find('/') {
+        let n = BigInt::from_str_radix(&s[..i], radix).ok()?;
+        let d = &s[i + 1..];
+        if d.starts_with('+') || d.starts_with('-') {
+            return None;
+        }
+        let d = BigInt::from_str_radix(d, radix).ok()?;
+        if d.is_zero() {
+            return None;
+        }
+        return Some(from_rational(BigRational::new(n, d)));
+    }
+    if radix == 10 && s.contains(['.', 'e', 'E']) {
+        if !s.contains(|c:char| c.is_ascii_digit()) {
+            return None;
+        }
+        if exact {
+            return parse_exact_decimal(s).map(from_rational);
+        }
+        return s.parse::<f64>().ok().map(Expr::Float);
+    }
+    BigInt::from_str_radix(s, radix).ok().map(from_bigint)
+}
+
+/// Parses the literal of a number, with optional prefixes for the radix
+/// (#x, #o, #b, #d) and the exactness (#e, #i): `42`, `-1/3`, `1.5e10`,
+/// `#x1F`, `#e1.5`...
+pub fn parse(s:&str) -> Option<Expr> {
+    let mut radix = 10;
+    let mut exactness = None;
+    let mut s = s;
+    while s.starts_with('#') {
+        match s.chars().nth(1) {
+            Some('x') | Some('X') => radix = 16,
+            Some('o') | Some('O') => radix = 8,
+            Some('b') | Some('B') => radix = 2,
+            Some('d') | Some('D') => radix = 10,
+            Some('e') | Some('E') => exactness = Some(true),
+            Some('i') | Some('I') => exactness = Some(false),
+            _ => return None
+        }
+        s = &s[2..];
+    }
+    let res = parse_real(s, radix, exactness == Some(true))?;
+    match exactness {
+        Some(true) => to_exact(&res),
+        Some(false) => to_inexact(&res),
+        None => Some(res)
+    }
+}
+
+/// Formats a float so it can't be mistaken for an exact number
+pub fn format_float(x:f64) -> String {
+    if x.is_nan() {
+        "+nan.0".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 {"+inf.0".to_string()} else {"-inf.0".to_string()}
+    } else {
+        format!("{:?}", x)
+    }
+}
+
+/// Converts an integer that doesn't fit in an i64, or a rational, to a
+/// float (approximately)
+pub fn to_f64(x:&Expr) -> Option<f64> {
+    to_num(x).map(|n| num_to_f64(&n))
+}
@@ -0,0 +1,134 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Ports, which characters are read from or written to.
+//!
+//! An input port reads a string, a file (read entirely when it is opened)
+//! or the standard input (a line at a time). An output port writes to a
+//! string, a file or the standard output.
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum Port {
+    /// Characters to read, the position of the next one, and whether
+    /// more are read from the standard input once they are consumed
+    Input(Vec<char>, usize, bool),
+    Stdout,
+    File(File),
+    /// Characters written so far
+    String(String),
+    /// A closed port, which was an input port or not
+    Closed(bool)
+}
+
+// Ports are only equal to themselves
+impl PartialEq for Port {
+    fn eq(&self, other:&Port) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Port {
+    /// An input port reading the string s
+    pub fn from_string(s:&str) -> Port {
+        Port::Input(s.chars().collect(), 0, false)
+    }
+
+    /// The input port reading the standard input
+    pub fn stdin() -> Port {
+        Port::Input(vec!(), 0, true)
+    }
+
+    pub fn open_input_file(path:&Path) -> io::Result<Port> {
+        Ok(Port::from_string(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn open_output_file(path:&Path) -> io::Result<Port> {
+        Ok(Port::File(File::create(path)?))
+    }
+
+    pub fn is_input(&self) -> bool {
+        matches!(*self, Port::Input(_, _, _) | Port::Closed(true))
+    }
+
+    pub fn is_output(&self) -> bool {
+        !self.is_input()
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(*self, Port::Closed(_))
+    }
+
+    /// Returns the next character without consuming it, or None at the
+    /// end of the input
+    pub fn peek_char(&mut self) -> io::Result<Option<char>> {
+        if let Port::Input(ref mut chars, ref mut pos, stdin) = *self {
+            if *pos == chars.len() && stdin {
+                let mut line = String::new();
+                io::stdin().lock().read_line(&mut line)?;
+                *chars = line.chars().collect();
+                *pos = 0;
+            }
+            return Ok(chars.get(*pos).cloned());
+        }
+        Ok(None)
+    }
+
+    /// Consumes the next character, returning None at the end of the input
+    pub fn read_char(&mut self) -> io::Result<Option<char>> {
+        let c = self.peek_char()?;
+        if let (Port::Input(_, ref mut pos, _), Some(_)) = (&mut *self, c) {
+            *pos += 1;
+        }
+        Ok(c)
+    }
+
+    /// Consumes the characters up to the end of the line, returning them
+    /// without the newline, or None at the end of the input
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        loop {
+            match self.read_char()? {
+                None if line.is_empty() => return Ok(None),
+                None | Some('\n') => return Ok(Some(line)),
+                Some(c) => line.push(c)
+            }
+        }
+    }
+
+    /// Writes s on an output port
+    pub fn write_str(&mut self, s:&str) -> io::Result<()> {
+        match *self {
+            Port::Stdout => {
+                let mut stdout = io::stdout();
+                stdout.write_all(s.as_bytes())?;
+                stdout.flush()
+            },
+            Port::File(ref mut f) => f.write_all(s.as_bytes()),
+            Port::String(ref mut content) => {
+                content.push_str(s);
+                Ok(())
+            },
+            _ => Ok(())
+        }
+    }
+
+    /// Closes the port, writing what remains to be written
+    pub fn close(&mut self) -> io::Result<()> {
+        if let Port::File(ref mut f) = *self {
+            f.flush()?;
+        }
+        let input = self.is_input();
+        *self = Port::Closed(input);
+        Ok(())
+    }
+}
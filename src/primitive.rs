@@ -0,0 +1,85 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Functions implemented in Rust, callable from Scheme.
+
+use expr::Expr;
+use eval::Context;
+use error::EvalError;
+
+use std::fmt;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::rc::Rc;
+
+/// Number of arguments accepted by a primitive
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    /// Between a minimum and a maximum, both inclusive
+    Range(usize, usize)
+}
+
+impl Arity {
+    pub fn accepts(&self, n:usize) -> bool {
+        match *self {
+            Arity::Exactly(m) => n == m,
+            Arity::AtLeast(m) => n >= m,
+            Arity::Range(min, max) => n >= min && n <= max
+        }
+    }
+}
+
+/// Signature of a primitive: it gets the context of the call and the
+/// values of its arguments
+pub type PrimitiveFn = dyn Fn(&Context, &[Rc<Expr>]) -> Result<Rc<Expr>, EvalError>;
+
+/// A native function, with its name (used in error messages) and the
+/// number of arguments it accepts
+#[derive(Clone)]
+pub struct Primitive {
+    pub name: String,
+    pub arity: Arity,
+    pub func: Rc<PrimitiveFn>
+}
+
+impl Primitive {
+    pub fn new<F>(name:&str, arity:Arity, f:F) -> Primitive
+        where F: Fn(&Context, &[Rc<Expr>]) -> Result<Rc<Expr>, EvalError> + 'static {
+        Primitive {
+            name: name.to_string(),
+            arity,
+            func: Rc::new(f)
+        }
+    }
+
+    /// Calls the primitive with already evaluated arguments, after
+    /// checking their number. Arity errors refer to the current
+    /// expression of the context, i.e. the call.
+    pub fn call(&self, c:&Context, args:&[Rc<Expr>]) -> Result<Rc<Expr>, EvalError> {
+        if self.arity.accepts(args.len()) {
+            (self.func)(c, args)
+        } else {
+            Err(EvalError::Arity(self.name.clone(), c.expr.clone()))
+        }
+    }
+}
+
+impl Debug for Primitive {
+    fn fmt(&self, formatter:&mut Formatter) -> fmt::Result {
+        write!(formatter, "Primitive({})", self.name)
+    }
+}
+
+// Two primitives are only equal if they share the same function
+impl PartialEq for Primitive {
+    fn eq(&self, other:&Primitive) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
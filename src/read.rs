@@ -7,122 +7,172 @@
     // (at your option) any later version.
 
 use lexer::Token;
+use lexer::Lexeme;
 use lexer::Lexer;
 use expr::Expr;
+use source::Span;
+use source::SourceMap;
+use error::EvalError;
 
 use std::rc::Rc;
+use std::cell::RefCell;
 use std::fs::File;
 use std::io::Read;
 
-fn read_quote<'a> (xs:&'a [Token])->(Expr, &'a [Token]) {
-    if xs.len() == 0 {
-        error! ("Error parsing quote: not enough arguments");
-        (Expr::Nil, &[])
-    } else {
-        let (e,r) = read_expr(&xs[0], &xs[1..]);
-        (Expr::Quote(Rc::new(e)), r)
-    }
-}
+// An expression read, and the tokens remaining after it
+type Parsed<'a> = Result<(Rc<Expr>, &'a [Lexeme]), EvalError>;
 
-fn read_unquote<'a> (xs:&'a [Token])->(Expr, &'a [Token]) {
-    if xs.len() == 0 {
-        error! ("Error parsing quote: not enough arguments");
-        (Expr::Nil, &[])
-    } else {
-        let (e,r) = read_expr(&xs[0], &xs[1..]);
-        (Expr::Unquote(Rc::new(e)), r)
-    }
+fn syntax_error<T>(span:&Span, reason:&str) -> Result<T, EvalError> {
+    Err(EvalError::Syntax(reason.to_string(), span.clone()))
 }
 
-fn read_quasiquote<'a> (xs:&'a [Token])->(Expr, &'a [Token]) {
+// Build a (quote x)-like form from the expression following a quote token
+fn read_quoted<'a> (name:&str,
+                    span:&Span,
+                    xs:&'a [Lexeme],
+                    map:&mut SourceMap,
+                    f:fn(Rc<Expr>) -> Expr) -> Parsed<'a> {
     if xs.len() == 0 {
-        error! ("Error parsing quote: not enough arguments");
-        (Expr::Nil, &[])
+        syntax_error(span, &format!("nothing to {}", name))
     } else {
-        let (e,r) = read_expr(&xs[0], &xs[1..]);
-        (Expr::Quasiquote(Rc::new(e)), r)
+        let (e,r) = read_expr(&xs[0], &xs[1..], map)?;
+        let end = map.get(&e).cloned();
+        let res = Rc::new(f(e));
+        match end {
+            Some(ref end) => map.insert(&res, span.to(end)),
+            None => map.insert(&res, span.clone())
+        }
+        Ok((res, r))
     }
 }
 
-fn read_paren<'a> (xs:&'a [Token])->(Expr,&'a[Token]) {
-    if xs.len() == 0 {
-        error! ("Error parsing '(: closing parenthesis not found");
-        (Expr::Nil,&[])
-    } else {
-        let x:&Token=&xs[0];
-        let xs = &xs[1..];
-        match *x {
-            Token::ClosingParen => (Expr::Nil, xs),
+// Elements of a list or a vector, expression following a dot, position of
+// the closing parenthesis and remaining tokens
+type Elems<'a> = (Vec<Rc<Expr>>, Option<Rc<Expr>>, Span, &'a [Lexeme]);
+
+// Read the elements up to the closing parenthesis
+fn read_elems<'a> (span:&Span, xs:&'a [Lexeme], map:&mut SourceMap) -> Result<Elems<'a>, EvalError> {
+    let mut elems:Vec<Rc<Expr>> = vec!();
+    let mut xs = xs;
+    loop {
+        if xs.is_empty() {
+            return syntax_error(span, "closing parenthesis not found");
+        }
+        let x:&Lexeme=&xs[0];
+        xs = &xs[1..];
+        match x.token {
+            Token::ClosingParen => return Ok((elems, None, x.span.clone(), xs)),
+            Token::Dot => {
+                if elems.is_empty() || xs.is_empty() {
+                    return syntax_error(&x.span, "misplaced dot");
+                }
+                let (tail, r) = read_expr (&xs[0], &xs[1..], map)?;
+                return match r.first() {
+                    Some(&Lexeme {token: Token::ClosingParen, span: ref end}) =>
+                        Ok((elems, Some(tail), end.clone(), &r[1..])),
+                    Some(next) => syntax_error(&next.span, "expected ) after the cdr of a pair"),
+                    None => syntax_error(span, "closing parenthesis not found")
+                };
+            },
             _ => {
-                let (e1, r1) = read_expr (x, xs);
-                let (e2, r2) = read_paren (r1);
-                (Expr::Cons(Rc::new(e1),
-                             Rc::new(e2)), r2)
+                let (e, r) = read_expr (x, xs, map)?;
+                elems.push(e);
+                xs = r;
             }
         }
     }
 }
 
-fn read_expr<'a> (x:&Token, xs:&'a [Token])->(Expr,&'a [Token]) {
-    match *x {
-        Token::Integer(x) => (Expr::Integer(x), xs),
-        Token::Float(x) => (Expr::Float(x), xs),
-        Token::Ident(ref x) => (Expr::Ident(x.clone ()), xs),
-        Token::String(ref x) => (Expr::String(x.clone()), xs),
-        Token::Quote => read_quote(xs),
-        Token::Quasiquote => read_quasiquote(xs),
-        Token::Unquote => read_unquote(xs),
-        Token::OpeningParen => read_paren(xs),
-        Token::ClosingParen => {
-            error!("Parse error: closing parenthesis doesn't match opening one");
-            (Expr::Nil, &[])
-        }
+// Read a list, which can end with a dotted pair: (a b . c)
+fn read_list<'a> (span:&Span, xs:&'a [Lexeme], map:&mut SourceMap) -> Parsed<'a> {
+    let (elems, tail, end, xs) = read_elems(span, xs, map)?;
+    let mut res = tail.unwrap_or_else(|| Rc::new(Expr::Nil));
+    for e in elems.into_iter().rev() {
+        res = Rc::new(Expr::Cons(e, res));
     }
+    map.insert(&res, span.to(&end));
+    Ok((res, xs))
 }
 
-pub fn read(xs: &[Token])-> Vec<Rc<Expr>> {
+// Read a vector: #(a b c)
+fn read_vector<'a> (span:&Span, xs:&'a [Lexeme], map:&mut SourceMap) -> Parsed<'a> {
+    let (elems, end, xs) = match read_elems(span, xs, map)? {
+        (elems, None, end, xs) => (elems, end, xs),
+        _ => return syntax_error(span, "dot in a vector")
+    };
+    let res = Rc::new(Expr::Vector(RefCell::new(elems)));
+    map.insert(&res, span.to(&end));
+    Ok((res, xs))
+}
+
+fn read_expr<'a> (x:&Lexeme, xs:&'a [Lexeme], map:&mut SourceMap) -> Parsed<'a> {
+    let e = match x.token {
+        Token::Integer(x) => Expr::Integer(x),
+        Token::Float(x) => Expr::Float(x),
+        Token::BigInt(ref x) => Expr::BigInt(x.clone()),
+        Token::Rational(ref x) => Expr::Rational(x.clone()),
+        Token::Ident(ref x) => Expr::Ident(x.clone ()),
+        Token::String(ref x) => Expr::String(x.clone()),
+        Token::Bool(b) => Expr::Bool(b),
+        Token::Char(c) => Expr::Char(c),
+        Token::Quote => return read_quoted("quote", &x.span, xs, map, Expr::Quote),
+        Token::Quasiquote => return read_quoted("quasiquote", &x.span, xs, map, Expr::Quasiquote),
+        Token::Unquote => return read_quoted("unquote", &x.span, xs, map, Expr::Unquote),
+        Token::UnquoteSplicing =>
+            return read_quoted("unquote-splicing", &x.span, xs, map, Expr::UnquoteSplicing),
+        Token::OpeningParen => return read_list(&x.span, xs, map),
+        Token::VectorParen => return read_vector(&x.span, xs, map),
+        Token::ClosingParen => return syntax_error(&x.span, "closing parenthesis doesn't match opening one"),
+        Token::Dot => return syntax_error(&x.span, "dot outside of a list")
+    };
+    let e = Rc::new(e);
+    map.insert(&e, x.span.clone());
+    Ok((e, xs))
+}
+
+/// Parse a list of tokens, recording the position of the expressions in map
+pub fn read_with_map(xs: &[Lexeme], map:&mut SourceMap) -> Result<Vec<Rc<Expr>>, EvalError> {
     let mut res:Vec<Rc<Expr>> = vec!();
     let mut tokens = xs;
-    if tokens.len() == 0 {
-        res
-    } else {
-        loop {
-            let (e,r) = read_expr (&tokens[0], &tokens[1..]);
-            res.push(Rc::new(e));
-            if r.len() == 0 {
-                return res;
-            } else {
-                tokens = r;
-            }
-        }
+    while !tokens.is_empty() {
+        let (e,r) = read_expr (&tokens[0], &tokens[1..], map)?;
+        res.push(e);
+        tokens = r;
     }
+    Ok(res)
 }
 
-pub fn read_str(s:&str) -> Vec<Rc<Expr>> {
-    let vchars:Vec<char> = s.chars().collect();
-    let mut v:Vec<Token> = vec!();
+pub fn read(xs: &[Lexeme]) -> Result<Vec<Rc<Expr>>, EvalError> {
+    read_with_map(xs, &mut SourceMap::new())
+}
+
+pub fn read_str(s:&str) -> Result<Vec<Rc<Expr>>, EvalError> {
+    read_source(s, "<input>", 1, &mut SourceMap::new())
+}
+
+/// Read source code, recording the position of its expressions in map.
+/// line is the number of the first line of src in file. Fails at the
+/// first syntax error.
+pub fn read_source(src:&str, file:&str, line:usize, map:&mut SourceMap) -> Result<Vec<Rc<Expr>>, EvalError> {
+    let vchars:Vec<char> = src.chars().collect();
+    let mut v:Vec<Lexeme> = vec!();
     {
         let mut l = Lexer::new(&vchars,&mut v);
+        l.with_file(file);
+        l.with_line(line);
         l.tokenize();
+        if let Some(err) = l.error.take() {
+            return Err(err);
+        }
     }
-    read(&v)
+    read_with_map(&v, map)
 }
 
-pub fn read_file(s:&str) -> Vec<Rc<Expr>> {
-    let res:Vec<Rc<Expr>> = vec!();
-    let r = File::open(s);
-    let mut file:&File;
-
-    match r {
-        Err(_) => {
-            error!("Error opening file {}", s);
-            return res;
-        },
-        Ok(ref f) => {
-            file = f;
-        }
-    }
+/// Read a file, recording the position of its expressions in map. Fails
+/// if the file can't be opened, isn't valid UTF-8 or has a syntax error.
+pub fn read_file(s:&str, map:&mut SourceMap) -> Result<Vec<Rc<Expr>>, EvalError> {
     let mut content = String::new();
-    file.read_to_string(&mut content).unwrap();
-    read_str (content.as_ref())
+    File::open(s).and_then(|mut f| f.read_to_string(&mut content))
+        .map_err(|e| EvalError::File(e.to_string(), Rc::new(Expr::String(s.to_string()))))?;
+    read_source(&content, s, 1, map)
 }
@@ -0,0 +1,151 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+use expr::Expr;
+
+use std::fmt;
+use std::fmt::Debug;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::rc::{Rc, Weak};
+use std::collections::HashMap;
+
+/// Text of some source code, kept by the spans in it to display excerpts
+#[derive(PartialEq)]
+pub struct SourceText {
+    /// Number of the first line of the text in its file
+    first_line: usize,
+    lines: Vec<String>
+}
+
+impl SourceText {
+    pub fn new(chars:&[char], first_line:usize) -> SourceText {
+        let text:String = chars.iter().collect();
+        SourceText {
+            first_line,
+            lines: text.lines().map(|l| l.to_string()).collect()
+        }
+    }
+}
+
+// The spans are printed without the whole text
+impl Debug for SourceText {
+    fn fmt(&self, formatter:&mut Formatter) -> fmt::Result {
+        write!(formatter, "SourceText({} lines from {})", self.lines.len(), self.first_line)
+    }
+}
+
+/// Position of a token or of an expression in the source code.
+///
+/// Lines and columns start at 1; the end position is exclusive.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Span {
+    pub file: Rc<String>,
+    pub text: Rc<SourceText>,
+    pub line: usize,
+    pub col: usize,
+    pub end_line: usize,
+    pub end_col: usize
+}
+
+impl Span {
+    /// Returns a span starting at the beginning of self and finishing at
+    /// the end of other
+    pub fn to(&self, other:&Span) -> Span {
+        Span {
+            file: self.file.clone(),
+            text: self.text.clone(),
+            line: self.line,
+            col: self.col,
+            end_line: other.end_line,
+            end_col: other.end_col
+        }
+    }
+
+    /// Returns the source line of the span, with the spanned part
+    /// underlined by carets, or None if it is not in the text
+    pub fn excerpt(&self) -> Option<String> {
+        let line = self.text.lines.get(self.line.checked_sub(self.text.first_line)?)?;
+        let width = line.chars().count() + 1;
+        let end = if self.end_line == self.line && self.end_col > self.col {
+            self.end_col
+        } else {
+            width
+        };
+        let mut carets = String::new();
+        for _ in 1..self.col {
+            carets.push(' ');
+        }
+        for _ in self.col..end.max(self.col + 1) {
+            carets.push('^');
+        }
+        Some(format!("{}\n{}", line, carets))
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, formatter:&mut Formatter) -> fmt::Result {
+        write!(formatter, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+/// Side table associating parsed expressions to their position in the
+/// source.
+///
+/// Expressions are identified by the address of their `Rc`. The table
+/// only keeps a weak reference to each of them: it doesn't keep them alive,
+/// but their address can't be reused by another expression while their
+/// entry is there. The entries of the dropped expressions are removed from
+/// time to time.
+#[derive(Debug,Default)]
+pub struct SourceMap {
+    spans: HashMap<usize, (Weak<Expr>, Span)>,
+    // number of entries above which the dropped expressions are removed
+    limit: usize
+}
+
+// Minimum number of entries before looking for dropped expressions
+const MIN_LIMIT:usize = 1024;
+
+fn key(e:&Rc<Expr>) -> usize {
+    &**e as *const Expr as usize
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap {
+            spans: HashMap::new(),
+            limit: MIN_LIMIT
+        }
+    }
+
+    pub fn insert(&mut self, e:&Rc<Expr>, span:Span) {
+        if self.spans.len() >= self.limit {
+            self.spans.retain(|_, (e, _)| e.strong_count() > 0);
+            self.limit = MIN_LIMIT.max(2 * self.spans.len());
+        }
+        self.spans.insert(key(e), (Rc::downgrade(e), span));
+    }
+
+    pub fn get(&self, e:&Rc<Expr>) -> Option<&Span> {
+        match self.spans.get(&key(e)) {
+            Some((e, span)) if e.strong_count() > 0 => Some(span),
+            _ => None
+        }
+    }
+
+    /// Number of expressions in the table, including the dropped ones
+    /// that have not been removed yet
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+}
@@ -0,0 +1,458 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Macros defined by `syntax-rules`: pattern matching on the form of a
+//! macro use, and expansion of the template of the first matching rule.
+//!
+//! Expansion is hygienic for the bindings a template introduces: they are
+//! renamed with a suffix unique to the expansion, and the "#:" prefix that
+//! can't be written in the source, so a temporary variable of the macro
+//! can't capture a variable of the same name given by the user. The other
+//! identifiers of the template, free in it, are resolved by the evaluator
+//! where the macro was defined, so a binding of the user can't capture them
+//! either.
+
+use expr::Expr;
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::cell::RefCell;
+
+/// A `(syntax-rules (literals...) (pattern template)...)` macro
+#[derive(Debug,PartialEq)]
+pub struct SyntaxRules {
+    pub literals: Vec<String>,
+    /// The identifier used as ellipsis, `...` unless another one is given
+    pub ellipsis: String,
+    pub rules: Vec<(Rc<Expr>, Rc<Expr>)>
+}
+
+// What a pattern variable matched: a single expression, or a sequence of
+// matches for a variable followed by an ellipsis
+#[derive(Clone)]
+enum Match {
+    One(Rc<Expr>),
+    Seq(Vec<Match>)
+}
+
+type Bindings = HashMap<String, Match>;
+
+// Names of the forms binding the identifiers given after their keyword
+const BINDING_FORMS:&[&str] = &[
+    "lambda", "define", "def", "defmacro", "define-syntax",
+    "let", "let*", "letrec", "letrec*", "let-syntax", "letrec-syntax", "guard"];
+
+// Name given to the identifier s of a template by the expansion whose
+// suffix is given
+fn renamed(s:&str, suffix:&str) -> String {
+    format!("#:{}{}", s, suffix)
+}
+
+// Name of the identifier of the template that s is the renaming of, if it
+// is one
+fn original<'a>(s:&'a str, suffix:&str) -> Option<&'a str> {
+    s.strip_prefix("#:")?.strip_suffix(suffix)
+}
+
+// Split a list into its elements and what ends it (nil for a proper list)
+fn split_list(e:&Rc<Expr>) -> (Vec<Rc<Expr>>, Rc<Expr>) {
+    let mut elems = vec!();
+    let mut l = e.clone();
+    while let Expr::Cons(ref car, ref cdr) = *l.clone() {
+        elems.push(car.clone());
+        l = cdr.clone();
+    }
+    (elems, l)
+}
+
+fn make_list(elems:Vec<Rc<Expr>>, tail:Rc<Expr>) -> Rc<Expr> {
+    let mut res = tail;
+    for e in elems.into_iter().rev() {
+        res = Rc::new(Expr::Cons(e, res));
+    }
+    res
+}
+
+fn ident(e:&Expr) -> Option<&str> {
+    match *e {
+        Expr::Ident(ref s) => Some(s),
+        _ => None
+    }
+}
+
+impl SyntaxRules {
+    /// Builds a macro from the arguments of a `syntax-rules` form:
+    /// `[ellipsis] (literals...) (pattern template)...`. Returns None if
+    /// they are ill-formed.
+    pub fn new(spec:&Rc<Expr>) -> Option<SyntaxRules> {
+        let (mut args, tail) = split_list(spec);
+        if *tail != Expr::Nil || args.is_empty() {
+            return None;
+        }
+        let ellipsis = match ident(&args[0]) {
+            Some(s) => {
+                let s = s.to_string();
+                args.remove(0);
+                s
+            },
+            None => "...".to_string()
+        };
+        if args.is_empty() {
+            return None;
+        }
+        let (literals, tail) = split_list(&args[0]);
+        if *tail != Expr::Nil {
+            return None;
+        }
+        let literals = literals.iter()
+            .map(|e| ident(e).map(|s| s.to_string()))
+            .collect::<Option<Vec<String>>>()?;
+        let mut rules = vec!();
+        for rule in &args[1..] {
+            let (parts, tail) = split_list(rule);
+            match (&parts[..], &*tail) {
+                ([pattern, template], Expr::Nil) if matches!(**pattern, Expr::Cons(_, _)) =>
+                    rules.push((pattern.clone(), template.clone())),
+                _ => return None
+            }
+        }
+        Some(SyntaxRules {
+            literals,
+            ellipsis,
+            rules
+        })
+    }
+
+    /// Expands a use of the macro, given its arguments (the form without
+    /// the keyword). suffix is used to rename the bindings introduced by
+    /// the template, and must be unique to this expansion. The other
+    /// identifiers of the template are replaced by what resolve returns for
+    /// them, if anything. Returns None if no rule matches.
+    pub fn expand(&self, args:&Rc<Expr>, suffix:&str, resolve:&dyn Fn(&str) -> Option<Rc<Expr>>) -> Option<Rc<Expr>> {
+        for (pattern, template) in &self.rules {
+            let mut bindings = Bindings::new();
+            // the keyword in the pattern is ignored
+            let pattern = match **pattern {
+                Expr::Cons(_, ref r) => r,
+                _ => continue
+            };
+            if self.match_pattern(pattern, args, &mut bindings) {
+                let res = self.instantiate(template, &bindings, suffix, true)?;
+                let mut introduced = HashSet::new();
+                binders(&res, suffix, &mut introduced);
+                return Some(rename(&res, suffix, &introduced, resolve));
+            }
+        }
+        None
+    }
+
+    fn is_ellipsis(&self, e:&Expr) -> bool {
+        ident(e) == Some(self.ellipsis.as_str())
+    }
+
+    // Pattern variables of a pattern
+    fn pattern_vars(&self, pattern:&Rc<Expr>, vars:&mut Vec<String>) {
+        match **pattern {
+            Expr::Ident(ref s) if s != "_" && !self.literals.contains(s) && *s != self.ellipsis =>
+                vars.push(s.clone()),
+            Expr::Cons(_, _) => {
+                let (elems, tail) = split_list(pattern);
+                for e in elems.iter().chain(Some(&tail)) {
+                    self.pattern_vars(e, vars);
+                }
+            },
+            Expr::Vector(ref v) => for e in v.borrow().iter() {
+                self.pattern_vars(e, vars);
+            },
+            _ => ()
+        }
+    }
+
+    fn match_pattern(&self, pattern:&Rc<Expr>, e:&Rc<Expr>, bindings:&mut Bindings) -> bool {
+        match **pattern {
+            Expr::Ident(ref s) if s == "_" => true,
+            Expr::Ident(ref s) if self.literals.contains(s) => ident(e) == Some(s.as_str()),
+            Expr::Ident(ref s) => {
+                bindings.insert(s.clone(), Match::One(e.clone()));
+                true
+            },
+            Expr::Cons(_, _) => {
+                let (pats, pat_tail) = split_list(pattern);
+                let (elems, tail) = split_list(e);
+                self.match_elems(&pats, &pat_tail, &elems, &tail, bindings)
+            },
+            Expr::Vector(ref pats) => match **e {
+                Expr::Vector(ref elems) => {
+                    let nil = Rc::new(Expr::Nil);
+                    self.match_elems(&pats.borrow(), &nil, &elems.borrow(), &nil, bindings)
+                },
+                _ => false
+            },
+            _ => pattern == e
+        }
+    }
+
+    // Match the elements of a list (or vector) against the elements of a
+    // pattern, one of which can be followed by an ellipsis, and then what
+    // remains of the list against the tail of the pattern
+    fn match_elems(&self,
+                   pats:&[Rc<Expr>],
+                   pat_tail:&Rc<Expr>,
+                   elems:&[Rc<Expr>],
+                   tail:&Rc<Expr>,
+                   bindings:&mut Bindings) -> bool {
+        let (before, repeated, after) = match pats.iter().position(|p| self.is_ellipsis(p)) {
+            Some(0) => return false,
+            Some(i) => (&pats[..i - 1], Some(&pats[i - 1]), &pats[i + 1..]),
+            None => (pats, None, &pats[..0])
+        };
+        let n_repeated = match repeated {
+            Some(_) if elems.len() >= before.len() + after.len() =>
+                elems.len() - before.len() - after.len(),
+            Some(_) => return false,
+            // without ellipsis, the elements left are matched by the tail
+            None if elems.len() >= before.len() => 0,
+            None => return false
+        };
+        for (p, e) in before.iter().zip(elems) {
+            if !self.match_pattern(p, e, bindings) {
+                return false;
+            }
+        }
+        if let Some(p) = repeated {
+            let mut vars = vec!();
+            self.pattern_vars(p, &mut vars);
+            let mut seqs:Vec<Vec<Match>> = vars.iter().map(|_| vec!()).collect();
+            for e in &elems[before.len()..before.len() + n_repeated] {
+                let mut b = Bindings::new();
+                if !self.match_pattern(p, e, &mut b) {
+                    return false;
+                }
+                for (var, seq) in vars.iter().zip(seqs.iter_mut()) {
+                    seq.push(b.remove(var).unwrap_or(Match::Seq(vec!())));
+                }
+            }
+            for (var, seq) in vars.into_iter().zip(seqs) {
+                bindings.insert(var, Match::Seq(seq));
+            }
+        }
+        let start = before.len() + n_repeated;
+        let after_elems = if repeated.is_some() {
+            &elems[start..start + after.len()]
+        } else {
+            &elems[start..start]
+        };
+        for (p, e) in after.iter().zip(after_elems) {
+            if !self.match_pattern(p, e, bindings) {
+                return false;
+            }
+        }
+        let rest = make_list(elems[start + after_elems.len()..].to_vec(), tail.clone());
+        self.match_pattern(pat_tail, &rest, bindings)
+    }
+
+    // Replace the pattern variables of a template by what they matched.
+    // The other identifiers are renamed, unless they are quoted (rename is
+    // false).
+    fn instantiate(&self, template:&Rc<Expr>, bindings:&Bindings, suffix:&str, rename:bool) -> Option<Rc<Expr>> {
+        let res = match **template {
+            Expr::Ident(ref s) => match bindings.get(s) {
+                Some(Match::One(e)) => return Some(e.clone()),
+                // a variable followed by an ellipsis in the pattern must be
+                // followed by one in the template too
+                Some(Match::Seq(_)) => return None,
+                None if rename => Expr::Ident(renamed(s, suffix)),
+                None => return Some(template.clone())
+            },
+            Expr::Cons(ref car, ref cdr) => {
+                // (... ...) is an ellipsis, (... template) a template in
+                // which ellipses are not special
+                if self.is_ellipsis(car) {
+                    if let Expr::Cons(ref t, ref r) = **cdr {
+                        if **r == Expr::Nil {
+                            let escaped = SyntaxRules {
+                                literals: self.literals.clone(),
+                                ellipsis: String::new(),
+                                rules: vec!()
+                            };
+                            return escaped.instantiate(t, bindings, suffix, rename);
+                        }
+                    }
+                }
+                let (elems, tail) = split_list(template);
+                let elems = self.instantiate_elems(&elems, bindings, suffix, rename)?;
+                return Some(make_list(elems, self.instantiate(&tail, bindings, suffix, rename)?));
+            },
+            Expr::Vector(ref v) => {
+                let elems = self.instantiate_elems(&v.borrow(), bindings, suffix, rename)?;
+                Expr::Vector(RefCell::new(elems))
+            },
+            Expr::Quote(ref e) => Expr::Quote(self.instantiate(e, bindings, suffix, false)?),
+            Expr::Quasiquote(ref e) => Expr::Quasiquote(self.instantiate(e, bindings, suffix, false)?),
+            Expr::Unquote(ref e) => Expr::Unquote(self.instantiate(e, bindings, suffix, true)?),
+            Expr::UnquoteSplicing(ref e) =>
+                Expr::UnquoteSplicing(self.instantiate(e, bindings, suffix, true)?),
+            _ => return Some(template.clone())
+        };
+        Some(Rc::new(res))
+    }
+
+    // Instantiate the elements of a list or a vector template; an element
+    // followed by n ellipses gives one element per match of the variables
+    // it contains, flattened n times
+    fn instantiate_elems(&self, elems:&[Rc<Expr>], bindings:&Bindings, suffix:&str, rename:bool) -> Option<Vec<Rc<Expr>>> {
+        let mut res = vec!();
+        let mut i = 0;
+        while i < elems.len() {
+            let depth = elems[i + 1..].iter().take_while(|e| self.is_ellipsis(e)).count();
+            if depth == 0 {
+                res.push(self.instantiate(&elems[i], bindings, suffix, rename)?);
+            } else {
+                self.instantiate_repeated(&elems[i], bindings, depth, suffix, rename, &mut res)?;
+            }
+            i += depth + 1;
+        }
+        Some(res)
+    }
+
+    fn instantiate_repeated(&self,
+                            template:&Rc<Expr>,
+                            bindings:&Bindings,
+                            depth:usize,
+                            suffix:&str,
+                            rename:bool,
+                            res:&mut Vec<Rc<Expr>>) -> Option<()> {
+        if depth == 0 {
+            res.push(self.instantiate(template, bindings, suffix, rename)?);
+            return Some(());
+        }
+        // the variables of the template matched by a sequence; they must
+        // all have matched the same number of elements
+        let mut vars = vec!();
+        self.pattern_vars(template, &mut vars);
+        let seqs:Vec<(&String, &Vec<Match>)> = vars.iter()
+            .filter_map(|v| match bindings.get(v) {
+                Some(Match::Seq(seq)) => Some((v, seq)),
+                _ => None
+            })
+            .collect();
+        let len = seqs.first()?.1.len();
+        if seqs.iter().any(|&(_, seq)| seq.len() != len) {
+            return None;
+        }
+        for i in 0..len {
+            let mut b = bindings.clone();
+            for &(v, seq) in &seqs {
+                b.insert(v.clone(), seq[i].clone());
+            }
+            self.instantiate_repeated(template, &b, depth - 1, suffix, rename, res)?;
+        }
+        Some(())
+    }
+}
+
+// Collect the renamed identifiers that are bound by a binding form of e
+fn binders(e:&Rc<Expr>, suffix:&str, res:&mut HashSet<String>) {
+    let (elems, tail) = match **e {
+        Expr::Cons(_, _) => split_list(e),
+        Expr::Vector(ref v) => (v.borrow().clone(), Rc::new(Expr::Nil)),
+        Expr::Quote(ref e)
+            | Expr::Quasiquote(ref e)
+            | Expr::Unquote(ref e)
+            | Expr::UnquoteSplicing(ref e) => return binders(e, suffix, res),
+        _ => return
+    };
+    let mut add = |e:&Rc<Expr>| if let Some(s) = ident(e) {
+        if original(s, suffix).is_some() {
+            res.insert(s.to_string());
+        }
+    };
+    let keyword = elems.first()
+        .and_then(|e| ident(e))
+        .map(|s| original(s, suffix).unwrap_or(s));
+    match keyword {
+        Some(k) if BINDING_FORMS.contains(&k) && elems.len() > 1 => {
+            let (first, rest) = (&elems[1], &elems[2..]);
+            let params = match k {
+                "lambda" | "defmacro" | "define" | "def" | "define-syntax" => {
+                    match **first {
+                        Expr::Ident(_) => {
+                            add(first);
+                            if k == "lambda" || k == "defmacro" {rest.first().cloned()} else {None}
+                        },
+                        // (define (name args...) body...)
+                        Expr::Cons(ref name, ref args) => {
+                            add(name);
+                            Some(args.clone())
+                        },
+                        _ => if k == "lambda" {Some(first.clone())} else {None}
+                    }
+                },
+                // (guard (var clause...) body...)
+                "guard" => {
+                    if let Expr::Cons(ref var, _) = **first {
+                        add(var);
+                    }
+                    None
+                },
+                // let forms, with a name for a named let
+                _ => {
+                    let bindings = match **first {
+                        Expr::Ident(_) => {
+                            add(first);
+                            rest.first().cloned()
+                        },
+                        _ => Some(first.clone())
+                    };
+                    if let Some(ref bindings) = bindings {
+                        for b in split_list(bindings).0 {
+                            if let Expr::Cons(ref name, _) = *b {
+                                add(name);
+                            }
+                        }
+                    }
+                    None
+                }
+            };
+            if let Some(params) = params {
+                let (params, tail) = split_list(&params);
+                for p in params.iter().chain(Some(&tail)) {
+                    add(p);
+                }
+            }
+        },
+        _ => ()
+    }
+    for e in elems.iter().chain(Some(&tail)) {
+        binders(e, suffix, res);
+    }
+}
+
+// Give back their name to the renamed identifiers that are not in
+// introduced, or replace them by what resolve gives for them
+fn rename(e:&Rc<Expr>, suffix:&str, introduced:&HashSet<String>, resolve:&dyn Fn(&str) -> Option<Rc<Expr>>) -> Rc<Expr> {
+    let res = match **e {
+        Expr::Ident(ref s) if !introduced.contains(s) => match original(s, suffix) {
+            Some(name) => return resolve(name).unwrap_or_else(|| Rc::new(Expr::Ident(name.to_string()))),
+            None => return e.clone()
+        },
+        Expr::Cons(_, _) => {
+            let (elems, tail) = split_list(e);
+            let elems = elems.iter().map(|e| rename(e, suffix, introduced, resolve)).collect();
+            return make_list(elems, rename(&tail, suffix, introduced, resolve));
+        },
+        Expr::Vector(ref v) =>
+            Expr::Vector(RefCell::new(v.borrow().iter().map(|e| rename(e, suffix, introduced, resolve)).collect())),
+        Expr::Quote(ref e) => Expr::Quote(rename(e, suffix, introduced, resolve)),
+        Expr::Quasiquote(ref e) => Expr::Quasiquote(rename(e, suffix, introduced, resolve)),
+        Expr::Unquote(ref e) => Expr::Unquote(rename(e, suffix, introduced, resolve)),
+        Expr::UnquoteSplicing(ref e) => Expr::UnquoteSplicing(rename(e, suffix, introduced, resolve)),
+        _ => return e.clone()
+    };
+    Rc::new(res)
+}
@@ -0,0 +1,95 @@
+    // rscheme -- a scheme interpreter written in Rust
+    // Copyright (C) {2015) Elizabeth Henry <liz.henry@ouvaton.org>
+
+    // This program is free software; you can redistribute it and/or modify
+    // it under the terms of the GNU General Public License as published by
+    // the Free Software Foundation; either version 2 of the License, or
+    // (at your option) any later version.
+
+//! Keys of hash tables.
+//!
+//! Two keys are the same if they are `equal?`: numbers of the same
+//! exactness and value, strings, symbols, characters and booleans with the
+//! same content, and lists or vectors of equal elements. Functions can't
+//! be keys.
+
+use expr::Expr;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A hashable copy of the expression used as a key
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub enum HashKey {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    BigInt(BigInt),
+    Rational(BigRational),
+    // bits of the float, with a single NaN
+    Float(u64),
+    Ident(String),
+    String(String),
+    Char(char),
+    // quote, quasiquote or unquote, and the quoted key
+    Quoted(&'static str, Box<HashKey>),
+    // elements of a list, and what ends it (Nil for a proper list)
+    List(Vec<HashKey>, Box<HashKey>),
+    Vector(Vec<HashKey>)
+}
+
+impl HashKey {
+    /// Returns the key of an expression, or None if it can't be a key
+    pub fn new(e:&Expr) -> Option<HashKey> {
+        let key = match *e {
+            Expr::Nil => HashKey::Nil,
+            Expr::Bool(b) => HashKey::Bool(b),
+            Expr::Integer(x) => HashKey::Integer(x),
+            Expr::BigInt(ref x) => HashKey::BigInt(x.clone()),
+            Expr::Rational(ref x) => HashKey::Rational(x.clone()),
+            Expr::Float(x) if x.is_nan() => HashKey::Float(f64::NAN.to_bits()),
+            // 0.0 and -0.0 are equal
+            Expr::Float(x) => HashKey::Float((x + 0.0).to_bits()),
+            Expr::Ident(ref s) => HashKey::Ident(s.clone()),
+            Expr::String(ref s) => HashKey::String(s.clone()),
+            Expr::Char(c) => HashKey::Char(c),
+            Expr::Quote(ref e) => HashKey::Quoted("quote", Box::new(HashKey::new(e)?)),
+            Expr::Quasiquote(ref e) => HashKey::Quoted("quasiquote", Box::new(HashKey::new(e)?)),
+            Expr::Unquote(ref e) => HashKey::Quoted("unquote", Box::new(HashKey::new(e)?)),
+            Expr::UnquoteSplicing(ref e) =>
+                HashKey::Quoted("unquote-splicing", Box::new(HashKey::new(e)?)),
+            Expr::Cons(_, _) => {
+                let mut elems = vec!();
+                let mut l = e;
+                while let Expr::Cons(ref car, ref cdr) = *l {
+                    elems.push(HashKey::new(car)?);
+                    l = cdr;
+                }
+                HashKey::List(elems, Box::new(HashKey::new(l)?))
+            },
+            Expr::Vector(ref v) => {
+                let elems:Option<Vec<HashKey>> = v.borrow().iter().map(|e| HashKey::new(e)).collect();
+                HashKey::Vector(elems?)
+            },
+            Expr::Lambda(_, _, _, _)
+                | Expr::Macro(_, _, _)
+                | Expr::SyntaxRules(_, _)
+                | Expr::Alias(_, _)
+                | Expr::Primitive(_)
+                | Expr::HashTable(_)
+                | Expr::Error(_)
+                | Expr::Port(_)
+                | Expr::Eof => return None
+        };
+        Some(key)
+    }
+}
+
+/// An entry of a hash table: its key and its value
+pub type Entry = (Rc<Expr>, Rc<Expr>);
+
+/// Content of a hash table, indexed by the hash key of the entries
+pub type Table = HashMap<HashKey, Entry>;
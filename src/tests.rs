@@ -9,29 +9,37 @@
 use eval;
 use read;
 use expr::Expr;
+use error::EvalError;
+use lexer::{Lexer, Lexeme};
+use interpreter::Interpreter;
+use convert::FromExpr;
+use primitive::Arity;
 
 use std::rc::Rc;
+use std::fmt::Debug;
+use std::fs;
+use std::env;
+use std::path::PathBuf;
 
 fn eval_str(s:&str) -> Rc<Expr> {
     let mut c = eval::Context::new();
-    let es = read::read_str(s);
+    let es = read::read_str(s).unwrap();
     for e in es {
-        c = c.eval_expr(e.clone());
+        c = c.eval_expr(e.clone()).unwrap();
     }
     c.expr.clone()
 }
 
 #[test]
 fn test_non_existing_file () {
-    let mut c = eval::Context::new();
-    c = c.eval_file("/some/non/existing/file/unless/we/are/really/unluck");
-    match *c.expr {
-        Expr::Nil => (),
-        _ => panic!("Return should be nil")
+    let c = eval::Context::new();
+    match c.eval_file("/some/non/existing/file/unless/we/are/really/unluck") {
+        Err(EvalError::File(_, _)) => (),
+        _ => panic!("Expected file error")
     }
 }
 
-fn compare (expected:&Expr, got:&Expr) {
+fn compare<T:PartialEq+Debug> (expected:&T, got:&T) {
     if expected != got {
         panic!("Expected: {:?}, got: {:?}", expected, got);
         }
@@ -106,7 +114,6 @@ fn test_macro () {
     compare (&exp, &e)
 }
 
-
 #[test]
 fn test_env_1 () {
     let s = "(defmacro defn (name args body)
@@ -156,3 +163,1101 @@ fn test_factorial () {
 }
              
 
+
+fn eval_str_err(s:&str) -> EvalError {
+    let mut c = eval::Context::new();
+    let es = match read::read_str(s) {
+        Ok(es) => es,
+        Err(err) => return err
+    };
+    for e in es {
+        match c.eval_expr(e.clone()) {
+            Ok(new_c) => c = new_c,
+            Err(err) => return err
+        }
+    }
+    panic!("Expected an error, got: {:?}", c.expr)
+}
+
+#[test]
+fn test_error_unbound () {
+    let err = eval_str_err("(_+ 1 foo)");
+    compare (&EvalError::UnboundVariable(Rc::new(Expr::Ident("foo".to_string()))), &err);
+}
+
+#[test]
+fn test_error_arity () {
+    match eval_str_err("(def f (lambda (x y) x))
+                        (f 1)") {
+        EvalError::Arity(..) => (),
+        e => panic!("Expected arity error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_error_type () {
+    compare (&EvalError::TypeMismatch("_+".to_string(), Rc::new(Expr::String("a".to_string()))),
+             &eval_str_err("(_+ 1 \"a\")"));
+}
+
+#[test]
+fn test_error_special_form () {
+    match eval_str_err("(if 1 2)") {
+        EvalError::BadSpecialForm(ref s, _) if s == "if" => (),
+        e => panic!("Expected ill-formed if, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_error_division_by_zero () {
+    match eval_str_err("(_/ 1 0)") {
+        EvalError::DivisionByZero(_) => (),
+        e => panic!("Expected division by zero, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_token_spans () {
+    let cs:Vec<char> = "(def x\n  \"a b\")".chars().collect();
+    let mut tokens:Vec<Lexeme> = vec!();
+    {
+        let mut l = Lexer::new(&cs, &mut tokens);
+        l.with_file("test.scm");
+        l.tokenize();
+    }
+    let positions:Vec<(usize,usize,usize,usize)> = tokens.iter()
+        .map(|t| (t.span.line, t.span.col, t.span.end_line, t.span.end_col))
+        .collect();
+    compare (&vec!((1,1,1,2), (1,2,1,5), (1,6,1,7), (2,3,2,8), (2,8,2,9)), &positions);
+    compare (&"test.scm".to_string(), &*tokens[0].span.file);
+}
+
+#[test]
+fn test_error_position () {
+    let cs:Vec<char> = "(def x 2)\n(_+ x\n    foo)".chars().collect();
+    let mut tokens:Vec<Lexeme> = vec!();
+    {
+        let mut l = Lexer::new(&cs, &mut tokens);
+        l.with_file("test.scm");
+        l.tokenize();
+    }
+    let mut c = eval::Context::new();
+    let es = read::read_with_map(&tokens, &mut c.shared.sources.borrow_mut()).unwrap();
+    c = c.eval_expr(es[0].clone()).unwrap();
+    let err = c.eval_expr(es[1].clone()).unwrap_err();
+    compare (&"test.scm:3:5: variable foo not found in environment\n    foo)\n    ^^^".to_string(),
+             &c.report(&err));
+}
+
+fn eval_str_init(s:&str) -> Rc<Expr> {
+    let mut c = eval::Context::new().eval_file("data/init.scm").unwrap();
+    let es = read::read_str(s).unwrap();
+    for e in es {
+        c = c.eval_expr(e.clone()).unwrap();
+    }
+    c.expr.clone()
+}
+
+#[test]
+fn test_tail_call_if () {
+    let s = "(def loop (lambda loop (n)
+                         (if (_= n 0)
+                             'done
+                             (loop (_- n 1)))))
+             (loop 1000000)";
+    compare (&Expr::Ident("done".to_string()), &eval_str(s));
+}
+
+#[test]
+fn test_tail_call_do () {
+    let s = "(def loop (lambda loop (n acc)
+                         (do (_+ n 1)
+                             (if (_= n 0)
+                                 acc
+                                 (loop (_- n 1) (_+ acc 2))))))
+             (loop 1000000 0)";
+    compare (&Expr::Integer(2000000), &eval_str(s));
+}
+
+#[test]
+fn test_long_lists () {
+    let s = "(defn range (n acc)
+               (if (= n 0)
+                   acc
+                   (range (- n 1) (cons n acc))))
+             (def xs (range 10000 ()))
+             (cons (count xs)
+                   (cons (car (reverse (map inc xs)))
+                         (cons (apply + xs) ())))";
+    let exp = Expr::Cons(Rc::new(Expr::Integer(10000)),
+                         Rc::new(Expr::Cons(Rc::new(Expr::Integer(10001)),
+                                            Rc::new(Expr::Cons(Rc::new(Expr::Integer(50005000)),
+                                                               Rc::new(Expr::Nil))))));
+    compare (&exp, &eval_str_init(s));
+}
+
+#[test]
+fn test_mutual_recursion () {
+    let s = "(defmacro defn (name args body)
+                            `(def ,name
+                                  (lambda ,name ,args
+                                          ,body)))
+             (defn even? (n) (if (_= n 0) 't (odd? (_- n 1))))
+             (defn odd? (n) (if (_= n 0) () (even? (_- n 1))))
+             (even? 10)";
+    compare (&Expr::Ident("t".to_string()), &eval_str(s));
+}
+
+#[test]
+fn test_closure_by_reference () {
+    let s = "(def y 1)
+             (def f (lambda (x) (_+ x y)))
+             (def y 2)
+             (f 1)";
+    compare (&Expr::Integer(3), &eval_str(s));
+}
+
+#[test]
+fn test_closure_lexical_scope () {
+    let s = "(def make-adder (lambda (x) (lambda (y) (_+ x y))))
+             (def add2 (make-adder 2))
+             (def call-with-x (lambda (x f) (f 10)))
+             (call-with-x 100 add2)";
+    compare (&Expr::Integer(12), &eval_str(s));
+}
+
+#[test]
+fn test_set_global () {
+    let s = "(def x 1)
+             (set! x (_+ x 1))
+             x";
+    compare (&Expr::Integer(2), &eval_str(s));
+}
+
+#[test]
+fn test_set_counter () {
+    let s = "(def make-counter (lambda (n)
+                                 (lambda ()
+                                   (set! n (_+ n 1)))))
+             (def c1 (make-counter 0))
+             (def c2 (make-counter 10))
+             (c1)
+             (c2)
+             (c1)
+             (_cons (c1) (c2))";
+    let exp = Expr::Cons(Rc::new(Expr::Integer(3)), Rc::new(Expr::Integer(12)));
+    compare (&exp, &eval_str(s));
+}
+
+#[test]
+fn test_set_shared_frame () {
+    let s = "(def make-account (lambda (balance)
+                                 (_cons (lambda (x) (set! balance (_+ balance x)))
+                                        (lambda () balance))))
+             (def account (make-account 100))
+             ((_car account) 50)
+             ((_cdr account))";
+    compare (&Expr::Integer(150), &eval_str(s));
+}
+
+#[test]
+fn test_set_unbound () {
+    match eval_str_err("(set! foo 1)") {
+        EvalError::UnboundVariable(_) => (),
+        e => panic!("Expected unbound variable, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_interpreter () {
+    let mut interp = Interpreter::new();
+    interp.set_global("xs", vec!(1, 2, 3)).unwrap();
+    interp.eval_str("(def ys (map inc xs))").unwrap();
+    let ys = interp.get_global("ys").unwrap();
+    compare (&vec!(2, 3, 4), &Vec::<i64>::from_expr(&ys).unwrap());
+    let s = interp.eval_str("(str \"a\" 1.5)").unwrap();
+    compare (&"a1.5".to_string(), &String::from_expr(&s).unwrap());
+}
+
+#[test]
+fn test_interpreter_error () {
+    let mut interp = Interpreter::without_prelude();
+    let err = interp.eval_str("(def x 1)\n(_+ x \"a\")").unwrap_err();
+    compare (&"<string>:2:7: invalid type of argument for _+: \"a\"\n(_+ x \"a\")\n      ^^^".to_string(),
+             &interp.report(&err));
+    assert!(interp.get_global("x").is_some());
+    assert!(i64::from_expr(&Rc::new(Expr::Float(1.0))).is_err());
+}
+
+#[test]
+fn test_primitive_first_class () {
+    let s = "(def map (lambda map (f xs)
+                        (if (_nil? xs)
+                            ()
+                            (_cons (f (_car xs)) (map f (_cdr xs))))))
+             (map _car '((1 2) (3 4)))";
+    let exp = Expr::Cons(Rc::new(Expr::Integer(1)),
+                         Rc::new(Expr::Cons(Rc::new(Expr::Integer(3)),
+                                            Rc::new(Expr::Nil))));
+    compare (&exp, &eval_str(s));
+    compare (&Expr::Integer(6), &eval_str_init("(apply + '(1 2 3))"));
+    compare (&Expr::Integer(3), &eval_str_init("(apply (lambda (x y) (+ x y)) '(1 2))"));
+}
+
+#[test]
+fn test_primitive_arity () {
+    match eval_str_err("(_car '(1 2) 3)") {
+        EvalError::Arity(ref s, _) if s == "_car" => (),
+        e => panic!("Expected arity error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_register_fn () {
+    let mut interp = Interpreter::new();
+    interp.register_fn("sum", Arity::AtLeast(0), |_, args| {
+        let mut res = 0;
+        for x in args {
+            res += i64::from_expr(x)?;
+        }
+        Ok(Rc::new(Expr::Integer(res)))
+    });
+    let res = interp.eval_str("(sum 1 2 (sum 3 4))").unwrap();
+    compare (&10, &i64::from_expr(&res).unwrap());
+    let res = interp.eval_str("(fold sum 0 '(1 2 3))").unwrap();
+    compare (&6, &i64::from_expr(&res).unwrap());
+    match interp.eval_str("(sum 1 \"a\")").unwrap_err().cause() {
+        &EvalError::TypeMismatch(_, _) => (),
+        e => panic!("Expected type error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_lambda_body () {
+    let s = "(def f (lambda (x)
+                      (def y (_* x 2))
+                      (_+ y 1)))
+             (f 20)";
+    compare (&Expr::Integer(41), &eval_str(s));
+    match eval_str_err("(lambda (x))") {
+        EvalError::BadSpecialForm(ref s, _) if s == "lambda" => (),
+        e => panic!("Expected ill-formed lambda, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_internal_define () {
+    let s = "(define (f n)
+               (define (even? n) (if (_= n 0) 't (odd? (_- n 1))))
+               (define (odd? n) (if (_= n 0) () (even? (_- n 1))))
+               (define x 10)
+               (_cons (even? n) x))
+             (define x 1)
+             (_cons (f 4) x)";
+    let exp = Expr::Cons(Rc::new(Expr::Cons(Rc::new(Expr::Ident("t".to_string())),
+                                            Rc::new(Expr::Integer(10)))),
+                         Rc::new(Expr::Integer(1)));
+    compare (&exp, &eval_str(s));
+    match eval_str_err("(define (f) (define y 1) y) (f) y") {
+        EvalError::UnboundVariable(_) => (),
+        e => panic!("Expected unbound variable, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_defn_body () {
+    let s = "(defn f (x)
+               (define y (* x 2))
+               (+ x y))
+             (f 3)";
+    compare (&Expr::Integer(9), &eval_str_init(s));
+}
+
+#[test]
+fn test_let () {
+    let s = "(def x 1)
+             (let ((x 2)
+                   (y x))
+               (define z 0)
+               (_+ x y))";
+    compare (&Expr::Integer(3), &eval_str(s));
+    match eval_str_err(&format!("{} z", s)) {
+        EvalError::UnboundVariable(_) => (),
+        e => panic!("Expected unbound variable, got {:?}", e)
+    }
+    compare (&Expr::Integer(4), &eval_str("(let* ((x 2) (y (_* x 2))) y)"));
+    compare (&Expr::Integer(2), &eval_str("(begin 1 2)"));
+    match eval_str_err("(let ((x)) x)") {
+        EvalError::BadSpecialForm(ref s, _) if s == "let" => (),
+        e => panic!("Expected ill-formed let, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_letrec () {
+    let s = "(letrec ((even? (lambda (n) (if (_= n 0) 't (odd? (_- n 1)))))
+                      (odd? (lambda (n) (if (_= n 0) () (even? (_- n 1))))))
+               (even? 100))";
+    compare (&Expr::Ident("t".to_string()), &eval_str(s));
+}
+
+#[test]
+fn test_named_let () {
+    let s = "(let loop ((i 0) (acc 0))
+               (if (_= i 100000)
+                   acc
+                   (loop (_+ i 1) (_+ acc i))))";
+    compare (&Expr::Integer(4999950000), &eval_str(s));
+    // the inits are evaluated in order
+    compare (&eval_str("'(2 1)"),
+             &eval_str("(def l ()) \
+                        (let loop ((a (set! l (_cons 1 l))) (b (set! l (_cons 2 l)))) l)"));
+}
+
+#[test]
+fn test_booleans () {
+    compare (&Expr::Bool(true), &eval_str("#t"));
+    compare (&Expr::Bool(false), &eval_str("#false"));
+    compare (&Expr::Bool(true), &eval_str("(_= 1 1)"));
+    compare (&Expr::Bool(false), &eval_str("(_< 2 1)"));
+    compare (&Expr::Bool(false), &eval_str("(_nil? '(1))"));
+    compare (&Expr::Integer(1), &eval_str("(if () 1 2)"));
+    compare (&Expr::Integer(2), &eval_str("(if #f 1 2)"));
+    compare (&Expr::Bool(true), &eval_str_init("(and (not #f) (or #f (boolean? #true)))"));
+    compare (&"#t".to_string(), &format!("{}", eval_str("#true")));
+}
+
+#[test]
+fn test_nil_is_false () {
+    let mut interp = Interpreter::new();
+    let res = interp.eval_str("(if () 1 2)").unwrap();
+    compare (&1, &i64::from_expr(&res).unwrap());
+    interp.set_nil_is_false(true);
+    let res = interp.eval_str("(if () 1 2)").unwrap();
+    compare (&2, &i64::from_expr(&res).unwrap());
+    let res = interp.eval_str("(if #f 1 2)").unwrap();
+    compare (&2, &i64::from_expr(&res).unwrap());
+    interp.set_global("b", true).unwrap();
+    let res = interp.eval_str("(not b)").unwrap();
+    compare (&false, &bool::from_expr(&res).unwrap());
+}
+
+#[test]
+fn test_number_literals () {
+    compare (&"1/3".to_string(), &format!("{}", eval_str("1/3")));
+    compare (&Expr::Integer(2), &eval_str("4/2"));
+    compare (&Expr::Integer(31), &eval_str("#x1F"));
+    compare (&Expr::Integer(-5), &eval_str("-5"));
+    compare (&Expr::Float(1e10), &eval_str("1e10"));
+    compare (&"3/2".to_string(), &format!("{}", eval_str("#e1.5")));
+    compare (&"1/10".to_string(), &format!("{}", eval_str("#e0.1")));
+    compare (&Expr::Bool(false), &eval_str("(string->number \"#e1e999999999\")"));
+    compare (&Expr::Float(2.0), &eval_str("#i2"));
+    compare (&"2.0".to_string(), &format!("{}", eval_str("2.0")));
+    compare (&Expr::Ident("-".to_string()), &eval_str("'-"));
+}
+
+#[test]
+fn test_numeric_tower () {
+    compare (&"100000000000000000000".to_string(),
+             &format!("{}", eval_str("(_* 10000000000 10000000000)")));
+    compare (&Expr::Integer(10000000000),
+             &eval_str("(_/ (_* 10000000000 10000000000) 10000000000)"));
+    compare (&"9223372036854775808".to_string(),
+             &format!("{}", eval_str("(_+ 9223372036854775807 1)")));
+    compare (&"1/3".to_string(), &format!("{}", eval_str("(_/ 1 3)")));
+    compare (&Expr::Integer(1), &eval_str("(_+ 1/3 2/3)"));
+    compare (&Expr::Float(0.75), &eval_str("(_+ 1/4 0.5)"));
+    compare (&Expr::Bool(true), &eval_str("(_< 1/3 0.34)"));
+    compare (&Expr::Bool(true), &eval_str("(_= 1/2 0.5)"));
+    compare (&Expr::Float(0.5), &eval_str("(exact->inexact 1/2)"));
+    compare (&"1/4".to_string(), &format!("{}", eval_str("(inexact->exact 0.25)")));
+    compare (&Expr::Integer(-1), &eval_str("(remainder -7 2)"));
+    compare (&Expr::Integer(1), &eval_str("(modulo -7 2)"));
+    compare (&Expr::Bool(true), &eval_str("(exact? 1/2)"));
+    match eval_str_err("(_/ 1/2 0)") {
+        EvalError::DivisionByZero(_) => (),
+        e => panic!("Expected division by zero, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_chars () {
+    compare (&Expr::Char('a'), &eval_str("#\\a"));
+    compare (&Expr::Char(' '), &eval_str("#\\space"));
+    compare (&Expr::Char('\n'), &eval_str("#\\newline"));
+    compare (&Expr::Char('A'), &eval_str("#\\x41"));
+    compare (&Expr::Char('('), &eval_str("(_car '(#\\( 1))"));
+    compare (&"#\\space".to_string(), &format!("{}", eval_str("#\\space")));
+    compare (&Expr::Integer(97), &eval_str("(char->integer #\\a)"));
+    compare (&Expr::Char('b'), &eval_str("(integer->char 98)"));
+    compare (&Expr::Char('A'), &eval_str("(char-upcase #\\a)"));
+    compare (&Expr::Bool(true), &eval_str("(char-alphabetic? #\\z)"));
+    compare (&Expr::Bool(false), &eval_str("(char-alphabetic? #\\1)"));
+    compare (&Expr::Bool(true), &eval_str("(char<? #\\a #\\b #\\c)"));
+    compare (&Expr::Bool(false), &eval_str("(char=? #\\a #\\a #\\b)"));
+    compare (&Expr::Bool(true), &eval_str("(char? #\\a)"));
+    compare (&Expr::String("ab".to_string()), &eval_str("(_str #\\a \"b\")"));
+    match eval_str_err("(char-upcase 1)") {
+        EvalError::TypeMismatch(ref s, _) if s == "char-upcase" => (),
+        e => panic!("Expected type error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_strings () {
+    compare (&Expr::Integer(3), &eval_str("(string-length \"été\")"));
+    compare (&Expr::Char('t'), &eval_str("(string-ref \"été\" 1)"));
+    compare (&Expr::String("ell".to_string()), &eval_str("(substring \"hello\" 1 4)"));
+    compare (&Expr::String("llo".to_string()), &eval_str("(substring \"hello\" 2)"));
+    compare (&Expr::String("abc".to_string()), &eval_str("(string-append \"a\" \"\" \"bc\")"));
+    compare (&Expr::String("ABC".to_string()), &eval_str("(string-upcase \"abc\")"));
+    compare (&Expr::Integer(255), &eval_str("(string->number \"ff\" 16)"));
+    compare (&Expr::Bool(false), &eval_str("(string->number \"abc\")"));
+    compare (&Expr::String("-101".to_string()), &eval_str("(number->string -5 2)"));
+    compare (&Expr::String("1/3".to_string()), &eval_str("(number->string (_/ 1 3))"));
+    compare (&Expr::Ident("foo".to_string()), &eval_str("(string->symbol \"foo\")"));
+    compare (&Expr::String("foo".to_string()), &eval_str("(symbol->string 'foo)"));
+    compare (&eval_str("'(\"a\" \"\" \"b\")"), &eval_str("(string-split \"a,,b\" #\\,)"));
+    compare (&eval_str("'(\"a\" \"b\")"), &eval_str("(string-split \"a::b\" \"::\")"));
+    compare (&Expr::Integer(2), &eval_str("(string-index \"abcb\" #\\c)"));
+    compare (&Expr::Integer(1), &eval_str("(string-index \"a1\" char-numeric?)"));
+    compare (&Expr::Bool(false), &eval_str("(string-index \"abc\" #\\z)"));
+    compare (&Expr::String("ab".to_string()), &eval_str("(list->string (string->list \"ab\"))"));
+    compare (&Expr::Bool(true), &eval_str("(string<? \"a\" \"ab\" \"b\")"));
+    compare (&Expr::Bool(false), &eval_str("(string=? \"a\" \"a\" \"b\")"));
+    match eval_str_err("(string-ref \"abc\" 3)") {
+        EvalError::OutOfRange(ref s, _) if s == "string-ref" => (),
+        e => panic!("Expected out of range error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_vectors () {
+    compare (&"#(1 \"a\" (2 3))".to_string(), &format!("{}", eval_str("#(1 \"a\" (2 3))")));
+    compare (&"#()".to_string(), &format!("{}", eval_str("#()")));
+    compare (&Expr::Integer(3), &eval_str("(vector-length #(1 2 3))"));
+    compare (&Expr::Integer(2), &eval_str("(vector-ref #(1 2 3) 1)"));
+    compare (&eval_str("#(0 0 0)"), &eval_str("(make-vector 3 0)"));
+    compare (&eval_str("#(1 x 3)"), &eval_str("(def v #(1 2 3)) (vector-set! v 1 'x) v"));
+    compare (&eval_str("#(7 7)"), &eval_str("(vector-fill! (make-vector 2) 7)"));
+    compare (&eval_str("'(1 2)"), &eval_str("(vector->list #(1 2))"));
+    compare (&eval_str("#(1 2)"), &eval_str("(list->vector '(1 2))"));
+    compare (&eval_str("#(2 4)"), &eval_str("(vector-map (lambda (x) (_* x 2)) #(1 2))"));
+    compare (&Expr::Bool(true), &eval_str("(vector? (vector 1 2))"));
+    match eval_str_err("(vector-ref #(1 2) 2)") {
+        EvalError::OutOfRange(ref s, _) if s == "vector-ref" => (),
+        e => panic!("Expected out of range error, got {:?}", e)
+    }
+    match eval_str_err("(make-vector 9223372036854775807)") {
+        EvalError::OutOfRange(ref s, _) if s == "make-vector" => (),
+        e => panic!("Expected out of range error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_hash_tables () {
+    let init = "(def t (make-hash-table)) \
+                (hash-table-set! t \"a\" 1) \
+                (hash-table-set! t '(1 x) 2) \
+                (hash-table-set! t 3 'three) \
+                (hash-table-set! t 3.0 'float) ";
+    let eval = |s:&str| eval_str(&format!("{}{}", init, s));
+    compare (&Expr::Integer(1), &eval("(hash-table-ref t \"a\")"));
+    compare (&Expr::Integer(2), &eval("(hash-table-ref t (_cons 1 '(x)))"));
+    compare (&Expr::Ident("three".to_string()), &eval("(hash-table-ref t 3)"));
+    compare (&Expr::Ident("float".to_string()), &eval("(hash-table-ref t 3.0)"));
+    compare (&Expr::Integer(4), &eval("(hash-table-count t)"));
+    compare (&Expr::Integer(0), &eval("(hash-table-ref/default t 'b 0)"));
+    compare (&Expr::Integer(5), &eval("(hash-table-ref t 'b (lambda () 5))"));
+    compare (&Expr::Integer(3), &eval("(hash-table-set! t \"a\" 7) (hash-table-delete! t 3) (hash-table-count t)"));
+    compare (&Expr::Bool(false), &eval("(hash-table-delete! t 3) (hash-table-contains? t 3)"));
+    compare (&Expr::Integer(3), &eval("(def n 0) \
+                                       (hash-table-walk t (lambda (k v) (if (_integer? v) (set! n (_+ n v)) n))) \
+                                       n"));
+    let single = "(hash-table-delete! t 3) (hash-table-delete! t 3.0) (hash-table-delete! t '(1 x)) ";
+    compare (&eval_str("'(\"a\")"), &eval(&format!("{}(hash-table-keys t)", single)));
+    compare (&Expr::Integer(1), &eval(&format!("{}(_cdr (_car (hash-table->alist t)))", single)));
+    match eval_str_err("(hash-table-ref (make-hash-table) 1)") {
+        EvalError::KeyNotFound(ref s, _) if s == "hash-table-ref" => (),
+        e => panic!("Expected key not found error, got {:?}", e)
+    }
+    match eval_str_err("(hash-table-set! (make-hash-table) _car 1)") {
+        EvalError::TypeMismatch(ref s, _) if s == "hash-table-set!" => (),
+        e => panic!("Expected type error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_dotted_pairs () {
+    compare (&eval_str("(_cons 1 2)"), &eval_str("'(1 . 2)"));
+    compare (&eval_str("(_cons 1 (_cons 2 3))"), &eval_str("'(1 2 . 3)"));
+    compare (&eval_str("'(1 2)"), &eval_str("'(1 . (2))"));
+    compare (&"(1 . 2)".to_string(), &format!("{}", eval_str("(_cons 1 2)")));
+    compare (&"(a b . c)".to_string(), &format!("{}", eval_str("'(a b . c)")));
+    compare (&"((1 . 2) 3)".to_string(), &format!("{}", eval_str("'((1 . 2) 3)")));
+    compare (&Expr::Float(0.5), &eval_str(".5"));
+    compare (&Expr::Ident("...".to_string()), &eval_str("'..."));
+    compare (&eval_str("'(1 (2 3))"), &eval_str("((lambda (x . rest) (_cons x (_cons rest '()))) 1 2 3)"));
+    compare (&Expr::Nil, &eval_str("((lambda (x . rest) rest) 1)"));
+    compare (&eval_str("'(2 3)"), &eval_str("(define (f x . rest) rest) (f 1 2 3)"));
+    compare (&eval_str("'(2 3)"), &eval_str("(_apply (lambda (x . rest) rest) '(1 2 3))"));
+    match eval_str_err("((lambda (x . rest) rest))") {
+        EvalError::Arity(_, _) => (),
+        e => panic!("Expected arity error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_unquote_splicing () {
+    compare (&eval_str("'(1 2 3 4)"), &eval_str("(def x '(2 3)) `(1 ,@x 4)"));
+    compare (&eval_str("'(1 2 3)"), &eval_str("(def x '(2 3)) `(1 ,@x)"));
+    compare (&eval_str("'(1 4)"), &eval_str("`(1 ,@'() 4)"));
+    compare (&eval_str("'(1 . 2)"), &eval_str("(def x 2) `(1 . ,x)"));
+    compare (&eval_str("#(1 2 3)"), &eval_str("(def x '(2 3)) `#(1 ,@x)"));
+    compare (&"(1 ,@x)".to_string(), &format!("{}", eval_str("'(1 ,@x)")));
+    compare (&eval_str("'(b . 3)"), &eval_str("(defmacro m (f & args) `(,f ,@args)) (m _cons 'b 3)"));
+    match eval_str_err("`(1 ,@2)") {
+        EvalError::TypeMismatch(ref s, _) if s == "unquote-splicing" => (),
+        e => panic!("Expected type error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_nested_quasiquote () {
+    // only the unquotes nested in as many unquotes as quasiquotes are evaluated
+    compare (&"(a `(b ,x))".to_string(), &format!("{}", eval_str("(def x 1) `(a `(b ,x))")));
+    compare (&"(a `(b ,1))".to_string(), &format!("{}", eval_str("(def x 1) `(a `(b ,,x))")));
+    compare (&"(a `(b ,(c 1)))".to_string(), &format!("{}", eval_str("(def x 1) `(a `(b ,(c ,x)))")));
+    compare (&"`(,@(1 2))".to_string(), &format!("{}", eval_str("(def x '(1 2)) ``(,@,x)")));
+}
+
+#[test]
+fn test_syntax_rules () {
+    let swap = "(define-syntax swap! \
+                  (syntax-rules () \
+                    ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp))))) ";
+    compare (&eval_str("'(2 1)"),
+             &eval_str(&format!("{}(def x 1) (def y 2) (swap! x y) (_cons x (_cons y '()))", swap)));
+    // the temporary variable of the macro doesn't capture the user's one
+    compare (&eval_str("'(2 1)"),
+             &eval_str(&format!("{}(def tmp 1) (def y 2) (swap! tmp y) (_cons tmp (_cons y '()))", swap)));
+    let my_or = "(define-syntax my-or \
+                   (syntax-rules () \
+                     ((_) #f) \
+                     ((_ e) e) \
+                     ((_ e r ...) (let ((t e)) (if t t (my-or r ...)))))) ";
+    compare (&Expr::Integer(5), &eval_str(&format!("{}(def t 5) (my-or #f t)", my_or)));
+    compare (&Expr::Bool(false), &eval_str(&format!("{}(my-or)", my_or)));
+    // literals, and ellipsis following a nested pattern
+    let my_cond = "(define-syntax my-cond \
+                     (syntax-rules (else) \
+                       ((_ (else e)) e) \
+                       ((_ (c e) clause ...) (if c e (my-cond clause ...))))) ";
+    compare (&Expr::Integer(2), &eval_str(&format!("{}(my-cond (#f 1) (else 2))", my_cond)));
+    compare (&eval_str("'((1 3) (2 4))"),
+             &eval_str("(define-syntax zip \
+                          (syntax-rules () ((_ (a b) ...) '((a ...) (b ...))))) \
+                        (zip (1 2) (3 4))"));
+    // elements after the ellipsis, and a dotted tail
+    compare (&eval_str("'(3 (1 2))"),
+             &eval_str("(define-syntax last (syntax-rules () ((_ x ... y) '(y (x ...))))) (last 1 2 3)"));
+    compare (&eval_str("'(2 3)"),
+             &eval_str("(define-syntax tail (syntax-rules () ((_ x . r) 'r))) (tail 1 2 3)"));
+    compare (&Expr::Integer(3),
+             &eval_str("(let-syntax ((foo (syntax-rules () ((_ x) (_+ x 1))))) (foo 2))"));
+    match eval_str_err(&format!("{}(swap! x)", swap)) {
+        EvalError::BadSpecialForm(ref s, _) if s == "swap!" => (),
+        e => panic!("Expected ill-formed form error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_macroexpand () {
+    let defs = "(defmacro m1 (x) `(m2 ,x)) \
+                (defmacro m2 (x) `(_+ ,x 1)) ";
+    compare (&"(m2 (m1 3))".to_string(), &format!("{}", eval_str(&format!("{}(macroexpand-1 '(m1 (m1 3)))", defs))));
+    compare (&"(_+ (m1 3) 1)".to_string(), &format!("{}", eval_str(&format!("{}(macroexpand '(m1 (m1 3)))", defs))));
+    compare (&"(f 1)".to_string(), &format!("{}", eval_str("(macroexpand '(f 1))")));
+    compare (&Expr::Integer(5), &eval_str(&format!("{}(m1 (m1 3))", defs)));
+    // the macros used by a top-level form are expanded once, before it is
+    // evaluated
+    compare (&Expr::Integer(1),
+             &eval_str("(def n 0) \
+                        (defmacro m (x) (begin (set! n (_+ n 1)) x)) \
+                        (define (f) (m 1)) \
+                        (f) (f) (f) \
+                        n"));
+    // but not quoted code or binding names
+    compare (&eval_str("'(m1 2)"), &eval_str(&format!("{}'(m1 2)", defs)));
+    compare (&Expr::Integer(2), &eval_str(&format!("{}(let ((m1 2)) m1)", defs)));
+    // macros defined by the form itself are expanded when it is evaluated
+    compare (&Expr::Integer(5), &eval_str("(begin (defmacro m3 (x) x) (m3 5))"));
+}
+
+#[test]
+fn test_gensym () {
+    compare (&"#:g1".to_string(), &format!("{}", eval_str("(gensym)")));
+    compare (&"#:tmp1".to_string(), &format!("{}", eval_str("(gensym \"tmp\")")));
+    compare (&Expr::Bool(false), &eval_str("(_= (gensym) (gensym))"));
+    compare (&Expr::Bool(true), &eval_str("(def x (gensym)) (_= x x)"));
+    // a gensym can't capture a variable of the code using the macro
+    compare (&eval_str("'(2 1)"),
+             &eval_str("(defmacro swap! (a b) \
+                          (let ((tmp (gensym))) \
+                            `(let ((,tmp ,a)) (set! ,a ,b) (set! ,b ,tmp)))) \
+                        (def tmp 1) (def y 2) \
+                        (swap! tmp y) \
+                        (_cons tmp (_cons y ()))"));
+    match eval_str_err("(gensym 'a)") {
+        EvalError::TypeMismatch(_, _) => (),
+        e => panic!("Expected type error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_call_cc () {
+    compare (&Expr::Integer(3), &eval_str("(_+ 1 (call/cc (lambda (k) 2)))"));
+    compare (&Expr::Integer(11), &eval_str("(_+ 1 (call/cc (lambda (k) (_+ 5 (k 10)))))"));
+    compare (&Expr::Nil, &eval_str("(call-with-current-continuation (lambda (k) (k) 1))"));
+    // early exit from a loop
+    compare (&Expr::Integer(-2),
+             &eval_str("(define (find-neg xs) \
+                          (call/cc (lambda (return) \
+                            (let loop ((xs xs)) \
+                              (if (_nil? xs) #f \
+                                  (begin (if (_< (_car xs) 0) (return (_car xs)) ()) \
+                                         (loop (_cdr xs)))))))) \
+                        (find-neg '(1 3 -2 4 -5))"));
+    // an outer continuation escapes through the inner call/cc
+    compare (&Expr::Integer(1),
+             &eval_str("(call/cc (lambda (outer) (call/cc (lambda (inner) (outer 1))) 2))"));
+    // continuations are only escaping
+    match eval_str_err("(def saved ()) (call/cc (lambda (k) (set! saved k))) (saved 1)").cause() {
+        &EvalError::Escape(_, _) => (),
+        e => panic!("Expected escape error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_dynamic_wind () {
+    let defs = "(def trace ()) \
+                (define (note x) (set! trace (_cons x trace))) ";
+    compare (&eval_str("'(after 2 before)"),
+             &eval_str(&format!("{}(dynamic-wind (lambda () (note 'before)) \
+                                                 (lambda () (note 2)) \
+                                                 (lambda () (note 'after))) \
+                                 trace", defs)));
+    // after is called when a continuation escapes from the thunk
+    compare (&eval_str("'(after before)"),
+             &eval_str(&format!("{}(call/cc (lambda (k) \
+                                   (dynamic-wind (lambda () (note 'before)) \
+                                                 (lambda () (k 1) (note 'not-reached)) \
+                                                 (lambda () (note 'after))))) \
+                                 trace", defs)));
+    compare (&Expr::Integer(3),
+             &eval_str("(dynamic-wind (lambda () 1) (lambda () 3) (lambda () 2))"));
+}
+
+#[test]
+fn test_guard () {
+    compare (&Expr::Integer(42), &eval_str("(guard (e (#t e)) (raise 42))"));
+    compare (&Expr::Integer(3), &eval_str("(guard (e (#t e)) 1 2 3)"));
+    compare (&eval_str("'(\"boom\" 1 2)"),
+             &eval_str("(guard (e ((error-object? e) \
+                                   (_cons (error-object-message e) (error-object-irritants e)))) \
+                          (error \"boom\" 1 2))"));
+    // clauses are like the ones of cond
+    compare (&Expr::Integer(2), &eval_str("(guard (e ((_= e 1) 'one) (else 2)) (raise 5))"));
+    compare (&Expr::Integer(6), &eval_str("(guard (e ((_+ e 1) => (lambda (x) (_* x 2)))) (raise 2))"));
+    // errors of the evaluator are error objects too
+    compare (&Expr::Bool(true), &eval_str("(guard (e ((error-object? e) #t)) (_car 1))"));
+    compare (&eval_str("'(undefined)"),
+             &eval_str("(guard (e (#t (error-object-irritants e))) (_+ 1 undefined))"));
+    // raised again when no clause applies
+    compare (&Expr::Integer(1),
+             &eval_str("(guard (e (#t e)) (guard (e ((_string? e) 2)) (raise 1)))"));
+    match eval_str_err("(guard (e ((_= e 2) 2)) (raise 1))") {
+        EvalError::Raised(_) => (),
+        e => panic!("Expected raised object, got {:?}", e)
+    }
+    // without handler, errors are unchanged
+    match eval_str_err("(guard (e (#f 1)) undefined)") {
+        EvalError::UnboundVariable(_) => (),
+        e => panic!("Expected unbound variable error, got {:?}", e)
+    }
+    match eval_str_err("(error \"boom\" 1)").cause() {
+        EvalError::Error(message, _) => compare (&"boom".to_string(), message),
+        e => panic!("Expected error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_exception_handler () {
+    // the handler of raise-continuable returns to the raise
+    compare (&Expr::Integer(43),
+             &eval_str("(with-exception-handler \
+                          (lambda (e) (_+ e 1)) \
+                          (lambda () (_+ 1 (raise-continuable 41))))"));
+    // handlers are called where the exception is raised, with the
+    // previous handlers installed
+    compare (&Expr::Integer(10),
+             &eval_str("(call/cc (lambda (k) \
+                          (with-exception-handler \
+                            (lambda (e) (k (_* e 10))) \
+                            (lambda () \
+                              (with-exception-handler \
+                                (lambda (e) (raise (_+ e 1))) \
+                                (lambda () (raise 0)))))))"));
+    compare (&Expr::Integer(1),
+             &eval_str("(call/cc (lambda (k) \
+                          (with-exception-handler \
+                            (lambda (e) (k 1)) \
+                            (lambda () (_car 1)))))"));
+    // a handler returning from raise is an error
+    match eval_str_err("(with-exception-handler (lambda (e) 0) (lambda () (raise 'oops)))").cause() {
+        EvalError::Raised(e) => match **e {
+            Expr::Error(EvalError::Error(_, _)) => (),
+            _ => panic!("Expected error object, got {:?}", e)
+        },
+        e => panic!("Expected raised error, got {:?}", e)
+    }
+    // it is called once
+    compare (&Expr::Integer(1),
+             &eval_str("(def n 0) \
+                        (guard (e (#t n)) \
+                          (with-exception-handler \
+                            (lambda (e) (set! n (_+ n 1))) \
+                            (lambda () (_+ 1 (_car 1)))))"));
+}
+
+// Writes files in a new temporary directory, returning its path
+fn write_files(dir:&str, files:&[(&str, &str)]) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rscheme-{}-{}", dir, std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    for &(name, content) in files {
+        fs::write(dir.join(name), content).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_load () {
+    let dir = write_files("load", &[
+        ("main.scm", "(def x 1) (load \"lib/a.scm\")"),
+        // relative to the directory of the file loading it
+        ("lib/a.scm", "(load \"b.scm\") (def y (_+ x z)) (define (f) (load \"missing.scm\"))"),
+        ("lib/b.scm", "(def z 2) (load \"b.scm\")"),
+        ("lib/c.scm", "(def w 4) 'done")]);
+    let main = dir.join("main.scm");
+    let main = main.to_str().unwrap();
+    // b.scm loads itself
+    match eval_str_err(&format!("(load \"{}\")", main)).cause() {
+        EvalError::File(_, name) => compare (&Expr::String("b.scm".to_string()), name),
+        e => panic!("Expected file error, got {:?}", e)
+    }
+    fs::write(dir.join("lib/b.scm"), "(def z 2)").unwrap();
+    compare (&Expr::Integer(3), &eval_str(&format!("(load \"{}\") y", main)));
+    // the value of load is the one of the last form
+    compare (&eval_str("'done"),
+             &eval_str(&format!("(load \"{}\")", dir.join("lib/c.scm").to_str().unwrap())));
+    match eval_str_err(&format!("(load \"{}\") (f)", main)).cause() {
+        EvalError::File(_, name) => compare (&Expr::String("missing.scm".to_string()), name),
+        e => panic!("Expected file error, got {:?}", e)
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_include () {
+    let dir = write_files("include", &[
+        ("defs.scm", "(def a 1) (defmacro twice (x) `(_+ ,x ,x))"),
+        ("lib/body.scm", "(_+ x 1)"),
+        ("self.scm", "(include \"self.scm\")")]);
+    let path = |name:&str| dir.join(name).to_str().unwrap().to_string();
+    compare (&Expr::Integer(2),
+             &eval_str(&format!("(include \"{}\") (twice a)", path("defs.scm"))));
+    // included in place of the form
+    compare (&Expr::Integer(4),
+             &eval_str(&format!("(define (f x) (include \"{}\")) (f 3)", path("lib/body.scm"))));
+    match eval_str_err(&format!("(include \"{}\")", path("self.scm"))).cause() {
+        EvalError::File(_, _) => (),
+        e => panic!("Expected file error, got {:?}", e)
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_libraries () {
+    let lib = "(def secret 1) \
+               (define-library (my lib) \
+                 (export f (rename g h) counter) \
+                 (begin (def secret 10) \
+                        (define (f) secret) \
+                        (define (g x) (_+ x secret)) \
+                        (def counter 0)))";
+    compare (&Expr::Integer(10), &eval_str(&format!("{} (import (my lib)) (f)", lib)));
+    compare (&Expr::Integer(11), &eval_str(&format!("{} (import (my lib)) (h 1)", lib)));
+    // the library has its own environment
+    compare (&Expr::Integer(1), &eval_str(&format!("{} (import (my lib)) secret", lib)));
+    compare (&Expr::Integer(10), &eval_str(&format!("{} (import (my lib)) (def secret 2) (f)", lib)));
+    compare (&Expr::Integer(11), &eval_str(&format!("{} (import (prefix (my lib) my-)) (my-h 1)", lib)));
+    compare (&Expr::Integer(11), &eval_str(&format!("{} (import (rename (my lib) (h add))) (add 1)", lib)));
+    compare (&Expr::Integer(10), &eval_str(&format!("{} (import (only (my lib) f)) (f)", lib)));
+    match eval_str_err(&format!("{} (import (only (my lib) f)) (h 1)", lib)) {
+        EvalError::UnboundVariable(_) => (),
+        e => panic!("Expected unbound variable error, got {:?}", e)
+    }
+    match eval_str_err(&format!("{} (import (except (my lib) f)) (f)", lib)) {
+        EvalError::UnboundVariable(_) => (),
+        e => panic!("Expected unbound variable error, got {:?}", e)
+    }
+    match eval_str_err(&format!("{} (import (only (my lib) g))", lib)).cause() {
+        EvalError::Library(_, _) => (),
+        e => panic!("Expected library error, got {:?}", e)
+    }
+    match eval_str_err("(import (no such library))").cause() {
+        EvalError::Library(_, _) => (),
+        e => panic!("Expected library error, got {:?}", e)
+    }
+    match eval_str_err("(define-library (bad) (export x))") {
+        EvalError::UnboundVariable(_) => (),
+        e => panic!("Expected unbound variable error, got {:?}", e)
+    }
+    // a library can import another one
+    compare (&Expr::Integer(20),
+             &eval_str(&format!("{} (define-library (other) (import (my lib)) (export k) \
+                                      (begin (define (k) (_+ (f) (f))))) \
+                                 (import (other)) (k)", lib)));
+}
+
+#[test]
+fn test_library_files () {
+    let dir = write_files("libraries", &[
+        ("main.scm", "(import (lib a)) (a)"),
+        ("lib/a.sld", "(define-library (lib a) (import (b)) (export a) (begin (define (a) (_+ (b) 1))))"),
+        ("b.sld", "(define-library (b) (export b) (begin (define (b) 41)))")]);
+    // relative to the file being loaded
+    let mut c = eval::Context::new().eval_file(dir.join("main.scm").to_str().unwrap()).unwrap();
+    compare (&Expr::Integer(42), &c.expr);
+    // or in the library path
+    c = eval::Context::new();
+    c.shared.library_path.borrow_mut().push(dir.clone());
+    c = c.eval_expr(read::read_str("(import (b))").unwrap().remove(0)).unwrap();
+    c = c.eval_expr(read::read_str("(b)").unwrap().remove(0)).unwrap();
+    compare (&Expr::Integer(41), &c.expr);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_prelude_libraries () {
+    let mut interp = Interpreter::new();
+    // redefining map doesn't change the functions of the prelude using it
+    interp.eval_str("(def map 0) (def count 1)").unwrap();
+    compare (&Expr::String("ab".to_string()), &*interp.eval_str("(str \"a\" \"b\")").unwrap());
+    compare (&eval_str("'(2 3)"), &interp.eval_str("(import (only (rscheme list) map)) (map inc '(1 2))").unwrap());
+}
+
+#[test]
+fn test_string_ports () {
+    let p = "(def p (open-input-string \"ab\\ncd\\n\\nlast\")) ";
+    compare (&Expr::Char('a'), &eval_str(&format!("{}(peek-char p) (read-char p)", p)));
+    compare (&eval_str("'(\"ab\" \"cd\" \"\" \"last\")"),
+             &eval_str(&format!("{}(_cons (read-line p) (_cons (read-line p) \
+                                  (_cons (read-line p) (_cons (read-line p) ()))))", p)));
+    compare (&Expr::Bool(true), &eval_str(&format!("{}(read-line p) (read-line p) (read-line p) \
+                                                      (read-line p) (eof-object? (read-line p))", p)));
+    compare (&Expr::Eof, &eval_str("(read-char (open-input-string \"\"))"));
+    compare (&Expr::String("ab\nc".to_string()),
+             &eval_str("(def p (open-output-string)) \
+                        (write-string \"ab\" p) (newline p) (write-char #\\c p) \
+                        (get-output-string p)"));
+    match eval_str_err("(def p (open-input-string \"a\")) (close-port p) (read-char p)") {
+        EvalError::TypeMismatch(_, _) => (),
+        e => panic!("Expected type error, got {:?}", e)
+    }
+    match eval_str_err("(write-string \"a\" (open-input-string \"\"))") {
+        EvalError::TypeMismatch(_, _) => (),
+        e => panic!("Expected type error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_output_port () {
+    // _print writes on the current output port
+    compare (&Expr::String("1 2".to_string()),
+             &eval_str("(with-output-to-string (lambda () (_print 1) (write-string \" \") (_print 2)))"));
+    let mut interp = Interpreter::new();
+    let res = interp.eval_str("(with-output-to-string (lambda () (println \"x =\" 3)))").unwrap();
+    compare (&Expr::String("x = 3\n".to_string()), &*res);
+    // it is restored when the thunk exits with an error
+    let res = interp.eval_str("(guard (e (#t #f)) (with-output-to-string (lambda () (_car 1)))) \
+                               (output-port? (current-output-port))").unwrap();
+    compare (&Expr::Bool(true), &*res);
+    compare (&Expr::Bool(false), &*interp.eval_str("(_string? (current-output-port))").unwrap());
+}
+
+#[test]
+fn test_file_ports () {
+    let dir = write_files("ports", &[("in.txt", "first\nsecond\n")]);
+    let path = |name:&str| dir.join(name).to_str().unwrap().to_string();
+    compare (&Expr::String("second".to_string()),
+             &eval_str(&format!("(def p (open-input-file \"{}\")) (read-line p) (read-line p)", path("in.txt"))));
+    eval_str(&format!("(def p (open-output-file \"{}\")) (write-string \"hello\" p) (close-port p)",
+                      path("out.txt")));
+    compare (&"hello".to_string(), &fs::read_to_string(dir.join("out.txt")).unwrap());
+    match eval_str_err(&format!("(open-input-file \"{}\")", path("missing.txt"))).cause() {
+        EvalError::File(_, _) => (),
+        e => panic!("Expected file error, got {:?}", e)
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_raised_error_location () {
+    let mut interp = Interpreter::without_prelude();
+    let err = interp.eval_source("(with-exception-handler (lambda (e) (raise e)) (lambda () (_car 1)))",
+                                 "t.scm", 1).unwrap_err();
+    compare (&"t.scm:1:65: invalid type of argument for _car: 1".to_string(), &format!("{}", err));
+    compare (&Some(65), &err.span().map(|span| span.col));
+    let err = interp.eval_str("(with-exception-handler (lambda (e) 0) (lambda () (_car 1)))").unwrap_err();
+    compare (&"exception handler returned #Error(invalid type of argument for _car: 1)".to_string(),
+             &format!("{}", err.cause()));
+}
+
+#[test]
+fn test_gensym_uninterned () {
+    match eval_str_err("(_= (string->symbol \"#:g1\") (gensym))") {
+        EvalError::TypeMismatch(ref s, _) if s == "string->symbol" => (),
+        e => panic!("Expected type error, got {:?}", e)
+    }
+    match eval_str_err("(string->symbol (symbol->string (gensym)))") {
+        EvalError::TypeMismatch(ref s, _) if s == "string->symbol" => (),
+        e => panic!("Expected type error, got {:?}", e)
+    }
+}
+
+#[test]
+fn test_library_macros () {
+    let lib = "(define-library (macros) \
+                 (export twice my-or) \
+                 (begin (define (double-form x) `(_+ ,x ,x)) \
+                        (defmacro twice (x) (double-form x)) \
+                        (define-syntax my-or \
+                          (syntax-rules () \
+                            ((_) #f) \
+                            ((_ e r ...) (if e e (my-or r ...)))))))";
+    compare (&Expr::Integer(4), &eval_str(&format!("{} (import (prefix (macros) m:)) (m:twice 2)", lib)));
+    compare (&Expr::Integer(3), &eval_str(&format!("{} (import (prefix (macros) m:)) (m:my-or #f 3)", lib)));
+    // the expansion of b:cond uses the cond of the library
+    let mut interp = Interpreter::new();
+    compare (&Rc::new(Expr::Integer(2)),
+             &interp.eval_str("(import (prefix (rscheme base) b:)) (def cond 5) (b:cond (#f 1) (#f 3) (else 2))").unwrap());
+}
+
+#[test]
+fn test_syntax_rules_renaming () {
+    // the variable of a guard is renamed
+    compare (&Expr::Integer(5),
+             &eval_str("(define-syntax safe (syntax-rules () ((_ v) (guard (e (#t v)) (raise 1))))) \
+                        (def e 5) (safe e)"));
+    // an identifier of the user ending like a renamed one keeps its binding
+    compare (&Expr::Integer(7),
+             &eval_str("(define-syntax m (syntax-rules () ((_ v) (let ((t 1)) v)))) \
+                        (def t~1 7) (m t~1)"));
+}
+
+#[test]
+fn test_source_map_drops_expressions () {
+    let mut interp = Interpreter::without_prelude();
+    for _ in 0..10000 {
+        interp.eval_str("(let ((x 1)) (_+ x 2))").unwrap();
+    }
+    // only the entries of the expressions still alive are kept
+    assert!(interp.context().shared.sources.borrow().len() < 5000);
+    // an error refers to the text it happened in, even if other code was
+    // evaluated since
+    interp.eval_str("(define (f)\n  (_car 5))").unwrap();
+    interp.eval_str("(define zzz 1)\n(define yyy 2)").unwrap();
+    let err = interp.eval_str("(f)").unwrap_err();
+    compare (&"<string>:2:9: invalid type of argument for _car: 5\n  (_car 5))\n        ^".to_string(),
+             &interp.report(&err));
+}
+
+#[test]
+fn test_expansion_local_bindings () {
+    let mut interp = Interpreter::new();
+    // a local binding shadows the macro of the same name
+    compare (&Rc::new(Expr::Integer(3)),
+             &interp.eval_str("(define (f or) (or 1 2)) (f (lambda (a b) (+ a b)))").unwrap());
+    compare (&Rc::new(Expr::Ident("fn".to_string())),
+             &interp.eval_str("(let ((and (lambda (x y) 'fn))) (and 1 2))").unwrap());
+    compare (&Rc::new(Expr::Integer(-1)),
+             &interp.eval_str("((lambda (when) (when 1 2)) -)").unwrap());
+    compare (&Rc::new(Expr::Integer(0)),
+             &interp.eval_str("(let unless ((n 3)) (if (= n 0) 0 (unless (- n 1))))").unwrap());
+    // but not outside of its scope
+    compare (&Rc::new(Expr::Integer(2)),
+             &interp.eval_str("(let ((x (let ((or cons)) (or 1 2)))) (or #f (cdr x)))").unwrap());
+}
+
+#[test]
+fn test_syntax_rules_free_identifiers () {
+    // the car of the template is the one where the macro is defined
+    compare (&Expr::Integer(1),
+             &eval_str("(define-syntax my-first (syntax-rules () ((_ x) (_car x)))) \
+                        (let ((_car _cdr)) (my-first '(1 2)))"));
+    // even when the macro is expanded inside a function
+    compare (&Expr::Integer(1),
+             &eval_str("(define-syntax my-first (syntax-rules () ((_ x) (_car x)))) \
+                        (define (f _car) (my-first '(1 2))) (f _cdr)"));
+    // a macro of a library can use what the library doesn't export, and
+    // set! its variables
+    let lib = "(define-library (counters) \
+                 (export count! get) \
+                 (begin (define n 0) \
+                        (define (priv2 x) (_* 2 x)) \
+                        (define (get) n) \
+                        (define-syntax count! \
+                          (syntax-rules () ((_ x) (set! n (_+ n (priv2 x))))))))";
+    compare (&Expr::Integer(6),
+             &eval_str(&format!("{} (import (counters)) (count! 1) (count! 2) (get)", lib)));
+}
+
+#[test]
+fn test_syntax_errors () {
+    let mut interp = Interpreter::without_prelude();
+    for &(src, expected) in &[
+        ("(_+ 1", "<string>:1:1: syntax error: closing parenthesis not found\n(_+ 1\n^"),
+        ("\"abc", "<string>:1:1: syntax error: can't find closing quote\n\"abc\n^^^^"),
+        ("#\\foo", "<string>:1:1: syntax error: invalid character: #\\foo\n#\\foo\n^^^^^"),
+        ("'(1 . 2 3)", "<string>:1:9: syntax error: expected ) after the cdr of a pair\n'(1 . 2 3)\n        ^"),
+        ("(_+ 1 2))", "<string>:1:9: syntax error: mismatched parenthesis: too many )s\n(_+ 1 2))\n        ^")] {
+        let err = interp.eval_str(src).unwrap_err();
+        compare (&expected.to_string(), &interp.report(&err));
+    }
+    // nothing is evaluated from source that can't be read
+    match interp.eval_str("(define x 1) (") {
+        Err(EvalError::Syntax(_, _)) => (),
+        e => panic!("Expected a syntax error, got {:?}", e)
+    }
+    assert!(interp.get_global("x").is_none());
+}
+
+#[test]
+fn test_import_shares_variables () {
+    let lib = "(define-library (counter) \
+                 (export counter bump) \
+                 (begin (define counter 0) \
+                        (define (bump) (set! counter (_+ counter 1)))))";
+    compare (&Expr::Integer(2),
+             &eval_str(&format!("{} (import (counter)) (bump) (bump) counter", lib)));
+    // also under another name
+    compare (&Expr::Integer(1),
+             &eval_str(&format!("{} (import (prefix (counter) c:)) (c:bump) c:counter", lib)));
+    // a definition of the importer shadows the import, without changing
+    // the library
+    compare (&Expr::Integer(1),
+             &eval_str(&format!("{} (import (counter)) (define counter 10) (bump) \
+                                 (import (rename (counter) (counter n))) n", lib)));
+}